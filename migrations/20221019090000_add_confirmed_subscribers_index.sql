@@ -0,0 +1,4 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS subscriptions_confirmed_id_idx
+    ON subscriptions (id)
+    WHERE status = 'confirmed';
@@ -0,0 +1,26 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS newsletter_issues (
+    newsletter_issue_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    published_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id)
+);
+
+-- One row per recipient of an issue.
+-- `status` is one of 'queued', 'sent', 'failed' or 'skipped_invalid_email'.
+CREATE TABLE IF NOT EXISTS issue_deliveries (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL,
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    failure_reason TEXT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+
+CREATE INDEX IF NOT EXISTS issue_deliveries_status_idx
+    ON issue_deliveries (newsletter_issue_id, status);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- Events reported by the email provider through its webhook.
+CREATE TABLE IF NOT EXISTS email_events (
+    email_event_id uuid NOT NULL,
+    event TEXT NOT NULL,
+    email TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    reason TEXT NULL,
+    link TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL,
+    PRIMARY KEY (email_event_id)
+);
+
+CREATE INDEX IF NOT EXISTS email_events_newsletter_issue_idx
+    ON email_events (newsletter_issue_id, event);
+
+CREATE INDEX IF NOT EXISTS issue_deliveries_provider_message_id_idx
+    ON issue_deliveries (provider_message_id);
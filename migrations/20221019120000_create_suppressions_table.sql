@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Addresses that must never be mailed, keyed by the SHA-256 of the
+-- normalised (trimmed, lowercased) email.
+CREATE TABLE IF NOT EXISTS suppressions (
+    email_hash TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    source TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (email_hash)
+);
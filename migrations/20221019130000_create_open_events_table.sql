@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+
+-- One row per pixel load; unique opens are counted per subscriber.
+CREATE TABLE IF NOT EXISTS open_events (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL,
+    opened_at timestamptz NOT NULL,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS open_events_issue_subscriber_idx
+    ON open_events (newsletter_issue_id, subscriber_id);
@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+
+-- One row per tracked redirect; `url` is the original destination.
+CREATE TABLE IF NOT EXISTS click_events (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL,
+    url TEXT NOT NULL,
+    clicked_at timestamptz NOT NULL,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS click_events_issue_url_idx
+    ON click_events (newsletter_issue_id, url);
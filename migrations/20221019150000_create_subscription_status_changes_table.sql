@@ -0,0 +1,23 @@
+-- Add migration script here
+-- Every status a subscriber moved through; `from_status` is NULL for the
+-- status they were created with.
+CREATE TABLE IF NOT EXISTS subscription_status_changes (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    changed_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS subscription_status_changes_subscriber_idx
+    ON subscription_status_changes (subscriber_id);
+CREATE INDEX IF NOT EXISTS subscription_status_changes_changed_at_idx
+    ON subscription_status_changes (changed_at);
+CREATE INDEX IF NOT EXISTS subscriptions_subscribed_at_idx ON subscriptions (subscribed_at);
+
+-- Backfill existing subscribers. When their status changed is unknown, so
+-- the transition out of `pending_confirmation` is dated at signup.
+INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+SELECT id, NULL, 'pending_confirmation', subscribed_at FROM subscriptions;
+INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+SELECT id, 'pending_confirmation', status, subscribed_at FROM subscriptions
+WHERE status <> 'pending_confirmation';
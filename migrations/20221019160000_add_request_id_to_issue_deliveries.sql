@@ -0,0 +1,3 @@
+-- Add migration script here
+-- The `X-Request-Id` of the publish request that queued each delivery.
+ALTER TABLE issue_deliveries ADD COLUMN IF NOT EXISTS request_id TEXT;
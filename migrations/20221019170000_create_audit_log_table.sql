@@ -0,0 +1,28 @@
+-- Add migration script here
+-- Privileged actions, in the order they happened. Rows are never updated or
+-- deleted: the trigger below rejects both.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL DEFAULT now(),
+    -- NULL until admin endpoints are authenticated.
+    actor TEXT,
+    action TEXT NOT NULL,
+    target TEXT,
+    ip TEXT,
+    request_id TEXT,
+    details JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX IF NOT EXISTS audit_log_action_idx ON audit_log (action, occurred_at);
+
+CREATE OR REPLACE FUNCTION audit_log_is_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_is_append_only();
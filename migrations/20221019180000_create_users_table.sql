@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS users (
+    user_id uuid PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    -- An Argon2id hash in PHC string format.
+    password_hash TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Links emailed to subscribers to download the data held about them. Only
+-- the SHA-256 of the token is stored.
+CREATE TABLE IF NOT EXISTS data_exports (
+    token_hash TEXT PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS data_exports_subscriber_idx ON data_exports (subscriber_id);
@@ -0,0 +1,8 @@
+-- Tokens go with their subscriber, like the rest of the subscription.
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT IF EXISTS subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS subscription_tokens_subscriber_idx
+    ON subscription_tokens (subscriber_id);
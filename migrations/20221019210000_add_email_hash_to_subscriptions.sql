@@ -0,0 +1,13 @@
+-- The `email_hash` of each subscriber's address, as computed by the
+-- application, so suppressions are matched with a single normalisation.
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS email_hash TEXT;
+
+-- Existing rows are backfilled in SQL, which agrees with the application
+-- for addresses with ASCII letters and no surrounding tabs or line breaks.
+UPDATE subscriptions
+    SET email_hash = encode(sha256(convert_to(lower(trim(email)), 'UTF8')), 'hex')
+    WHERE email_hash IS NULL;
+
+ALTER TABLE subscriptions ALTER COLUMN email_hash SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS subscriptions_email_hash_idx ON subscriptions (email_hash);
@@ -0,0 +1,14 @@
+-- The `email_hash` of each event's address, as computed by the application,
+-- so exports and erasure find a subscriber's events with the same
+-- normalisation as their subscription.
+ALTER TABLE email_events ADD COLUMN IF NOT EXISTS email_hash TEXT;
+
+-- Existing rows are backfilled in SQL, which agrees with the application
+-- for addresses with ASCII letters and no surrounding tabs or line breaks.
+UPDATE email_events
+    SET email_hash = encode(sha256(convert_to(lower(trim(email)), 'UTF8')), 'hex')
+    WHERE email_hash IS NULL;
+
+ALTER TABLE email_events ALTER COLUMN email_hash SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS email_events_email_hash_idx ON email_events (email_hash);
@@ -0,0 +1,3 @@
+-- The client address a trusted proxy forwarded the request for. `ip` is the
+-- address the request came from, which the client cannot forge.
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS forwarded_for TEXT;
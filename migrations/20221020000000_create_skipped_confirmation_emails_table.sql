@@ -0,0 +1,13 @@
+-- One row per confirmation email that was not sent, as issue_deliveries
+-- records skipped issues. `status` is 'skipped_suppressed'.
+CREATE TABLE IF NOT EXISTS skipped_confirmation_emails (
+    skipped_confirmation_email_id uuid NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (skipped_confirmation_email_id)
+);
+
+CREATE INDEX IF NOT EXISTS skipped_confirmation_emails_subscriber_idx
+    ON skipped_confirmation_emails (subscriber_id);
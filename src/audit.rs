@@ -0,0 +1,88 @@
+use crate::request_id::RequestId;
+use crate::startup::TrustedProxies;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use sqlx::PgExecutor;
+use std::future::{ready, Ready};
+use std::net::SocketAddr;
+
+/// Who performed an audited action, as far as the request tells us.
+///
+/// Admins do not log in as users yet: the protected endpoints share one
+/// API token. `actor` is therefore `None` for HTTP requests, and only the
+/// client address identifies the caller. The admin CLI records the OS user
+/// as `cli:<user>`.
+#[derive(Clone, Debug)]
+pub struct AuditActor {
+    pub actor: Option<String>,
+    /// The address the request came from.
+    pub ip: Option<String>,
+    /// The client address a trusted proxy forwarded the request for.
+    /// Forwarding headers from anyone else are ignored, as any client can
+    /// set them.
+    pub forwarded_for: Option<String>,
+}
+
+impl FromRequest for AuditActor {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let peer_ip = request.peer_addr().map(|address| address.ip());
+        let from_trusted_proxy = request
+            .app_data::<web::Data<TrustedProxies>>()
+            .zip(peer_ip)
+            .is_some_and(|(proxies, ip)| proxies.0.contains(&ip));
+        let ip = peer_ip.map(|ip| ip.to_string());
+        let forwarded_for = if from_trusted_proxy {
+            request
+                .connection_info()
+                .realip_remote_addr()
+                .map(|address| match address.parse::<SocketAddr>() {
+                    Ok(address) => address.ip().to_string(),
+                    Err(_) => address.to_owned(),
+                })
+                // Without a forwarding header, this is the proxy itself.
+                .filter(|address| Some(address) != ip.as_ref())
+        } else {
+            None
+        };
+        ready(Ok(Self {
+            actor: None,
+            ip,
+            forwarded_for,
+        }))
+    }
+}
+
+/// Appends an entry to `audit_log`, stamped with the current request id.
+///
+/// `action` is a dotted `<subject>.<verb>` name, e.g. `newsletter.published`.
+/// `target` identifies what was acted on; it must not be personal data, so
+/// email addresses are recorded by their `email_hash`.
+#[tracing::instrument(name = "Record an audit log entry", skip(executor, actor, details))]
+pub async fn record_audit_event(
+    executor: impl PgExecutor<'_>,
+    actor: &AuditActor,
+    action: &str,
+    target: Option<&str>,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log
+            (occurred_at, actor, action, target, ip, forwarded_for, request_id, details)
+        VALUES (now(), $1, $2, $3, $4, $5, $6, $7)
+        "#,
+        actor.actor,
+        action,
+        target,
+        actor.ip,
+        actor.forwarded_for,
+        RequestId::current().map(|request_id| request_id.to_string()),
+        details,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
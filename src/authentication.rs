@@ -0,0 +1,54 @@
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use secrecy::{ExposeSecret, Secret};
+
+/// Whether `request` carries `Authorization: Bearer <token>` for exactly
+/// `token`.
+pub fn has_bearer_token(request: &HttpRequest, token: &Secret<String>) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|candidate| {
+            constant_time_eq(candidate.as_bytes(), token.expose_secret().as_bytes())
+        })
+}
+
+/// Compares two byte strings without short-circuiting on the first
+/// mismatch, so response timings do not leak the secret.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, has_bearer_token};
+    use actix_web::test::TestRequest;
+    use secrecy::Secret;
+
+    #[test]
+    fn constant_time_eq_compares_contents_and_length() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret!"));
+    }
+
+    #[test]
+    fn only_the_exact_bearer_token_is_accepted() {
+        let token = Secret::new("secret".to_string());
+        let request = |value: &str| {
+            TestRequest::default()
+                .insert_header(("Authorization", value))
+                .to_http_request()
+        };
+
+        assert!(has_bearer_token(&request("Bearer secret"), &token));
+        assert!(!has_bearer_token(&request("Bearer secreT"), &token));
+        assert!(!has_bearer_token(&request("Basic secret"), &token));
+        assert!(!has_bearer_token(
+            &TestRequest::default().to_http_request(),
+            &token
+        ));
+    }
+}
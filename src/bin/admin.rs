@@ -0,0 +1,20 @@
+use clap::Parser;
+use zero2prod::cli::{run, Cli};
+use zero2prod::configuration::get_configuration_with;
+use zero2prod::telemetry::{get_subscriber, init_subscriber};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let configuration = get_configuration_with(&cli.config)?;
+    // Results go to stdout; logs stay on stderr, out of the way of scripts.
+    let subscriber = get_subscriber(
+        "zero2prod-admin".into(),
+        "warn".into(),
+        std::io::stderr,
+        &configuration.telemetry,
+    );
+    init_subscriber(subscriber);
+
+    run(cli, &configuration, &mut std::io::stdout().lock()).await
+}
@@ -0,0 +1,252 @@
+use super::output::{print_record, print_records, timestamp, Record};
+use super::Context;
+use crate::audit::record_audit_event;
+use crate::routes::{publish_issue, BodyData, Content, PublishedIssue};
+use crate::startup::{get_connection_pool, get_email_client, get_tracking, DeliveryAbort};
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum IssuesCommand {
+    /// Lists newsletter issues, newest first, with their deliveries.
+    List {
+        #[clap(long, default_value_t = 20)]
+        limit: i64,
+    },
+    /// Publishes an issue to every confirmed subscriber.
+    Publish {
+        #[clap(long)]
+        title: String,
+        /// File holding the HTML body.
+        #[clap(long, value_name = "PATH")]
+        html_file: PathBuf,
+        /// File holding the plain text body.
+        #[clap(long, value_name = "PATH")]
+        text_file: PathBuf,
+        #[clap(long)]
+        track_opens: bool,
+        #[clap(long)]
+        track_clicks: bool,
+    },
+    /// Cancels the queued and failed deliveries of an issue, and those never
+    /// recorded, so they are not retried. Emails already handed to the
+    /// provider are not recalled.
+    Cancel { newsletter_issue_id: Uuid },
+}
+
+#[derive(serde::Serialize)]
+struct Issue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    deliveries: i64,
+    queued: i64,
+    failed: i64,
+}
+
+impl Record for Issue {
+    const COLUMNS: &'static [&'static str] = &[
+        "newsletter_issue_id",
+        "title",
+        "published_at",
+        "deliveries",
+        "queued",
+        "failed",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.newsletter_issue_id.to_string(),
+            self.title.clone(),
+            timestamp(&self.published_at),
+            self.deliveries.to_string(),
+            self.queued.to_string(),
+            self.failed.to_string(),
+        ]
+    }
+}
+
+impl Record for PublishedIssue {
+    const COLUMNS: &'static [&'static str] =
+        &["newsletter_issue_id", "status", "sent", "failed", "skipped"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.newsletter_issue_id.to_string(),
+            self.status.into(),
+            self.deliveries.sent.to_string(),
+            self.deliveries.failed.to_string(),
+            self.deliveries.skipped.to_string(),
+        ]
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Cancelled {
+    newsletter_issue_id: Uuid,
+    /// Deliveries moved to `cancelled`.
+    cancelled: u64,
+}
+
+impl Record for Cancelled {
+    const COLUMNS: &'static [&'static str] = &["newsletter_issue_id", "cancelled"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.newsletter_issue_id.to_string(),
+            self.cancelled.to_string(),
+        ]
+    }
+}
+
+pub(super) async fn run(
+    command: IssuesCommand,
+    context: &Context<'_>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let pool = &context.pool;
+    match command {
+        IssuesCommand::List { limit } => {
+            let issues = sqlx::query_as!(
+                Issue,
+                r#"
+                SELECT
+                    i.newsletter_issue_id,
+                    i.title,
+                    i.published_at,
+                    COUNT(d.subscriber_id) AS "deliveries!",
+                    COUNT(*) FILTER (WHERE d.status = 'queued') AS "queued!",
+                    COUNT(*) FILTER (WHERE d.status = 'failed') AS "failed!"
+                FROM newsletter_issues i
+                LEFT JOIN issue_deliveries d USING (newsletter_issue_id)
+                GROUP BY i.newsletter_issue_id
+                ORDER BY i.published_at DESC
+                LIMIT $1
+                "#,
+                limit
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to list newsletter issues")?;
+            print_records(out, context.format, &issues)
+        }
+        IssuesCommand::Publish {
+            title,
+            html_file,
+            text_file,
+            track_opens,
+            track_clicks,
+        } => {
+            let body = BodyData {
+                title,
+                content: Content {
+                    html: read(&html_file)?,
+                    text: read(&text_file)?,
+                },
+                track_opens,
+                track_clicks,
+            };
+            let configuration = context.configuration;
+            let email_client = get_email_client(&configuration.email_client);
+            let (tracking, tracking_flusher) = get_tracking(
+                &configuration.tracking,
+                get_connection_pool(&configuration.database),
+            );
+            let outcome = publish_issue(
+                pool,
+                &email_client,
+                &configuration.application.base_url,
+                &tracking,
+                &context.actor,
+                &body,
+                &DeliveryAbort::never(),
+            )
+            .await;
+            tracking_flusher.shutdown().await;
+            let published = outcome?;
+            print_record(out, context.format, &published)?;
+            if published.deliveries.failed > 0 {
+                anyhow::bail!(
+                    "Some deliveries failed, run `queue retry-failed --issue {}`",
+                    published.newsletter_issue_id
+                );
+            }
+            Ok(())
+        }
+        IssuesCommand::Cancel {
+            newsletter_issue_id,
+        } => {
+            let mut transaction = pool.begin().await?;
+            sqlx::query!(
+                "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1",
+                newsletter_issue_id
+            )
+            .fetch_optional(&mut transaction)
+            .await
+            .context("Failed to look the newsletter issue up")?
+            .with_context(|| format!("There is no newsletter issue {}", newsletter_issue_id))?;
+            let cancelled = sqlx::query!(
+                r#"
+                UPDATE issue_deliveries
+                SET status = 'cancelled', updated_at = now()
+                WHERE newsletter_issue_id = $1 AND status IN ('queued', 'failed')
+                "#,
+                newsletter_issue_id
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to cancel the deliveries")?
+            .rows_affected();
+            // Subscribers that an interrupted publish never reached.
+            let cancelled = cancelled
+                + sqlx::query!(
+                    r#"
+                    INSERT INTO issue_deliveries (
+                        newsletter_issue_id, subscriber_id, subscriber_email, status,
+                        created_at, updated_at
+                    )
+                    SELECT i.newsletter_issue_id, s.id, s.email, 'cancelled', now(), now()
+                    FROM newsletter_issues i, subscriptions s
+                    WHERE i.newsletter_issue_id = $1
+                        AND s.status = 'confirmed'
+                        AND COALESCE(
+                            (SELECT MAX(changed_at) FROM subscription_status_changes
+                                WHERE subscriber_id = s.id),
+                            s.subscribed_at
+                        ) <= i.published_at
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    newsletter_issue_id
+                )
+                .execute(&mut transaction)
+                .await
+                .context("Failed to cancel the unrecorded deliveries")?
+                .rows_affected();
+            record_audit_event(
+                &mut transaction,
+                &context.actor,
+                "newsletter.cancelled",
+                Some(&newsletter_issue_id.to_string()),
+                serde_json::json!({ "cancelled": cancelled }),
+            )
+            .await
+            .context("Failed to record the cancellation in the audit log")?;
+            transaction.commit().await?;
+            print_record(
+                out,
+                context.format,
+                &Cancelled {
+                    newsletter_issue_id,
+                    cancelled,
+                },
+            )
+        }
+    }
+}
+
+fn read(path: &PathBuf) -> anyhow::Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
@@ -0,0 +1,92 @@
+//! `zero2prod-admin`: manages subscribers, issues, users and the delivery
+//! queue straight from the database of the configured environment, and
+//! seeds databases for development.
+
+mod issues;
+mod output;
+mod queue;
+mod seed;
+mod subscribers;
+mod users;
+
+pub use issues::IssuesCommand;
+pub use output::OutputFormat;
+pub use queue::QueueCommand;
+pub use seed::SeedArgs;
+pub use subscribers::SubscribersCommand;
+pub use users::UsersCommand;
+
+use crate::audit::AuditActor;
+use crate::configuration::Settings;
+use crate::startup::get_connection_pool;
+use sqlx::PgPool;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(clap::Parser, Debug)]
+#[clap(
+    name = "zero2prod-admin",
+    version,
+    about = "Administers the newsletter"
+)]
+pub struct Cli {
+    /// Extra configuration file, layered over `configuration/`. Repeatable.
+    #[clap(long = "config", global = true, value_name = "PATH")]
+    pub config: Vec<PathBuf>,
+    /// How results are printed.
+    #[clap(long, global = true, value_enum, default_value = "table")]
+    pub format: OutputFormat,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Lists, inspects and changes subscribers.
+    #[clap(subcommand)]
+    Subscribers(SubscribersCommand),
+    /// Lists, publishes and cancels newsletter issues.
+    #[clap(subcommand)]
+    Issues(IssuesCommand),
+    /// Manages admin users.
+    #[clap(subcommand)]
+    Users(UsersCommand),
+    /// Inspects and retries pending deliveries.
+    #[clap(subcommand)]
+    Queue(QueueCommand),
+    /// Fills an empty database with realistic, reproducible data.
+    Seed(SeedArgs),
+}
+
+/// What every command runs with.
+struct Context<'a> {
+    configuration: &'a Settings,
+    pool: PgPool,
+    /// Changes made from the command line are audited as the OS user.
+    actor: AuditActor,
+    format: OutputFormat,
+}
+
+/// Runs `cli`, printing its results to `out`.
+pub async fn run(cli: Cli, configuration: &Settings, out: &mut dyn Write) -> anyhow::Result<()> {
+    let context = Context {
+        configuration,
+        pool: get_connection_pool(&configuration.database),
+        actor: AuditActor {
+            actor: Some(format!(
+                "cli:{}",
+                std::env::var("USER").unwrap_or_else(|_| "unknown".into())
+            )),
+            ip: None,
+            forwarded_for: None,
+        },
+        format: cli.format,
+    };
+    match cli.command {
+        Command::Subscribers(command) => subscribers::run(command, &context, out).await,
+        Command::Issues(command) => issues::run(command, &context, out).await,
+        Command::Users(command) => users::run(command, &context, out).await,
+        Command::Queue(command) => queue::run(command, &context, out).await,
+        Command::Seed(args) => seed::run(args, &context, out).await,
+    }
+}
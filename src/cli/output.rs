@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+use std::io::Write;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns, for people.
+    Table,
+    /// Pretty-printed JSON, for scripts.
+    Json,
+}
+
+/// Something a command prints: as a row of a table, or serialized as is.
+pub trait Record: serde::Serialize {
+    const COLUMNS: &'static [&'static str];
+
+    /// One cell per column.
+    fn cells(&self) -> Vec<String>;
+}
+
+/// Prints `records` as a JSON array or as a table with a header.
+pub fn print_records<R: Record>(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    records: &[R],
+) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Table => {
+            let header = R::COLUMNS.iter().map(|c| c.to_string()).collect();
+            let rows: Vec<Vec<String>> = std::iter::once(header)
+                .chain(records.iter().map(Record::cells))
+                .collect();
+            write_aligned(out, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints `record` as a JSON object or as one `column  value` line per
+/// column.
+pub fn print_record<R: Record>(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    record: &R,
+) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, record)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Table => {
+            let rows: Vec<Vec<String>> = R::COLUMNS
+                .iter()
+                .zip(record.cells())
+                .map(|(column, cell)| vec![column.to_string(), cell])
+                .collect();
+            write_aligned(out, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_aligned(out: &mut dyn Write, rows: &[Vec<String>]) -> std::io::Result<()> {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+pub fn timestamp(at: &DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// `-` stands for a missing value.
+pub fn optional<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "-".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{print_record, print_records, OutputFormat, Record};
+
+    #[derive(serde::Serialize)]
+    struct Row {
+        name: &'static str,
+        count: i64,
+    }
+
+    impl Record for Row {
+        const COLUMNS: &'static [&'static str] = &["name", "count"];
+
+        fn cells(&self) -> Vec<String> {
+            vec![self.name.into(), self.count.to_string()]
+        }
+    }
+
+    fn rows() -> [Row; 2] {
+        [
+            Row {
+                name: "confirmed",
+                count: 1200,
+            },
+            Row {
+                name: "bounced",
+                count: 3,
+            },
+        ]
+    }
+
+    #[test]
+    fn tables_align_their_columns() {
+        let mut out = Vec::new();
+
+        print_records(&mut out, OutputFormat::Table, &rows()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "name       count\nconfirmed  1200\nbounced    3\n"
+        );
+    }
+
+    #[test]
+    fn single_records_are_printed_one_column_per_line() {
+        let mut out = Vec::new();
+
+        print_record(&mut out, OutputFormat::Table, &rows()[1]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "name   bounced\ncount  3\n"
+        );
+    }
+
+    #[test]
+    fn json_output_is_the_serialized_records() {
+        let mut out = Vec::new();
+
+        print_records(&mut out, OutputFormat::Json, &rows()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            json[0],
+            serde_json::json!({ "name": "confirmed", "count": 1200 })
+        );
+    }
+}
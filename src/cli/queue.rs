@@ -0,0 +1,156 @@
+use super::output::{optional, print_records, timestamp, Record};
+use super::Context;
+use crate::audit::record_audit_event;
+use crate::routes::{get_issues_with_undelivered_subscribers, retry_failed_deliveries};
+use crate::startup::{get_connection_pool, get_email_client, get_tracking};
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use uuid::Uuid;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum QueueCommand {
+    /// Lists the issues with queued or failed deliveries.
+    Inspect,
+    /// Sends an issue again to the subscribers who are still confirmed and
+    /// did not get it: their delivery failed, was left queued for an hour,
+    /// or was never recorded because publishing was cut short.
+    RetryFailed {
+        /// Only retry this issue, rather than every issue with failures.
+        #[clap(long, value_name = "NEWSLETTER_ISSUE_ID")]
+        issue: Option<Uuid>,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct PendingIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    queued: i64,
+    failed: i64,
+    /// When the oldest of these deliveries last changed.
+    oldest_updated_at: DateTime<Utc>,
+}
+
+impl Record for PendingIssue {
+    const COLUMNS: &'static [&'static str] = &[
+        "newsletter_issue_id",
+        "title",
+        "queued",
+        "failed",
+        "oldest_updated_at",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.newsletter_issue_id.to_string(),
+            self.title.clone(),
+            self.queued.to_string(),
+            self.failed.to_string(),
+            timestamp(&self.oldest_updated_at),
+        ]
+    }
+}
+
+#[derive(serde::Serialize)]
+struct RetryOutcome {
+    newsletter_issue_id: Uuid,
+    retried: usize,
+    /// Why the retry did not go through, if it did not.
+    error: Option<String>,
+}
+
+impl Record for RetryOutcome {
+    const COLUMNS: &'static [&'static str] = &["newsletter_issue_id", "retried", "error"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.newsletter_issue_id.to_string(),
+            self.retried.to_string(),
+            optional(&self.error),
+        ]
+    }
+}
+
+pub(super) async fn run(
+    command: QueueCommand,
+    context: &Context<'_>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let pool = &context.pool;
+    match command {
+        QueueCommand::Inspect => {
+            let issues = sqlx::query_as!(
+                PendingIssue,
+                r#"
+                SELECT
+                    i.newsletter_issue_id,
+                    i.title,
+                    COUNT(*) FILTER (WHERE d.status = 'queued') AS "queued!",
+                    COUNT(*) FILTER (WHERE d.status = 'failed') AS "failed!",
+                    MIN(d.updated_at) AS "oldest_updated_at!"
+                FROM issue_deliveries d
+                JOIN newsletter_issues i USING (newsletter_issue_id)
+                WHERE d.status IN ('queued', 'failed')
+                GROUP BY i.newsletter_issue_id
+                ORDER BY MIN(d.updated_at)
+                "#
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to inspect the delivery queue")?;
+            print_records(out, context.format, &issues)
+        }
+        QueueCommand::RetryFailed { issue } => {
+            let issues = match issue {
+                Some(issue) => vec![issue],
+                None => get_issues_with_undelivered_subscribers(pool)
+                    .await
+                    .context("Failed to find undelivered issues")?,
+            };
+
+            let configuration = context.configuration;
+            let email_client = get_email_client(&configuration.email_client);
+            let (tracking, tracking_flusher) = get_tracking(
+                &configuration.tracking,
+                get_connection_pool(&configuration.database),
+            );
+            let mut outcomes = Vec::with_capacity(issues.len());
+            for newsletter_issue_id in issues {
+                let outcome = retry_failed_deliveries(
+                    pool,
+                    &email_client,
+                    &configuration.application.base_url,
+                    &tracking,
+                    newsletter_issue_id,
+                )
+                .await;
+                let retried = match &outcome {
+                    Ok(retried) => *retried,
+                    Err(_) => 0,
+                };
+                record_audit_event(
+                    pool,
+                    &context.actor,
+                    "deliveries.retried",
+                    Some(&newsletter_issue_id.to_string()),
+                    serde_json::json!({ "succeeded": outcome.is_ok() }),
+                )
+                .await
+                .context("Failed to record the retry in the audit log")?;
+                outcomes.push(RetryOutcome {
+                    newsletter_issue_id,
+                    retried,
+                    error: outcome.err().map(|e| format!("{:#}", e)),
+                });
+            }
+            tracking_flusher.shutdown().await;
+
+            print_records(out, context.format, &outcomes)?;
+            if outcomes.iter().any(|o| o.error.is_some()) {
+                anyhow::bail!("Some deliveries could not be retried");
+            }
+            Ok(())
+        }
+    }
+}
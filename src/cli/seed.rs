@@ -0,0 +1,777 @@
+use super::output::{print_record, Record};
+use super::Context;
+use crate::audit::record_audit_event;
+use crate::suppression::email_hash;
+use anyhow::Context as _;
+use chrono::{DateTime, Duration, Utc};
+use fake::faker::lorem::en::{Paragraph, Sentence, Words};
+use fake::faker::name::en::{FirstName, LastName};
+use fake::Fake;
+use rand07::distributions::Alphanumeric;
+use rand07::rngs::StdRng;
+use rand07::{Rng, SeedableRng};
+use sqlx::{Postgres, Transaction};
+use std::io::Write;
+use uuid::Uuid;
+
+/// Subscribers generated, and written, at a time.
+const BATCH_SIZE: usize = 1_000;
+
+/// How subscribers are spread across statuses, in percent.
+const STATUS_SHARES: &[(&str, u32)] = &[
+    ("confirmed", 70),
+    ("pending_confirmation", 12),
+    ("unsubscribed", 13),
+    ("bounced", 5),
+];
+
+/// How deliveries that did not bounce ended up, in percent.
+const DELIVERY_SHARES: &[(&str, u32)] = &[
+    ("delivered", 94),
+    ("sent", 3),
+    ("soft_bounced", 2),
+    ("failed", 1),
+];
+
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.net", "example.org"];
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 15_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/106.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko)",
+    "Mozilla/5.0 (Windows NT 5.1; rv:11.0) Gecko Firefox/11.0 (via ggpht.com GoogleImageProxy)",
+];
+
+#[derive(clap::Args, Debug)]
+pub struct SeedArgs {
+    /// Subscribers to create, across every status.
+    #[clap(long, default_value_t = 1_000)]
+    subscribers: usize,
+    /// Issues to publish, spread over the period.
+    #[clap(long, default_value_t = 20)]
+    issues: usize,
+    /// How many days of history to generate.
+    #[clap(long, default_value_t = 365)]
+    days: i64,
+    /// The same seed and arguments always produce the same data.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// When the history ends, as an RFC 3339 timestamp. Defaults to the
+    /// start of the current day, UTC, so reruns on a given day match.
+    #[clap(long)]
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, Default)]
+struct Seeded {
+    seed: u64,
+    subscribers: usize,
+    confirmed: usize,
+    issues: usize,
+    deliveries: usize,
+    opens: usize,
+    clicks: usize,
+}
+
+impl Record for Seeded {
+    const COLUMNS: &'static [&'static str] = &[
+        "seed",
+        "subscribers",
+        "confirmed",
+        "issues",
+        "deliveries",
+        "opens",
+        "clicks",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.seed.to_string(),
+            self.subscribers.to_string(),
+            self.confirmed.to_string(),
+            self.issues.to_string(),
+            self.deliveries.to_string(),
+            self.opens.to_string(),
+            self.clicks.to_string(),
+        ]
+    }
+}
+
+/// Fills an empty database with a realistic history: subscribers signing
+/// up at a growing pace, confirming, leaving or bouncing, and receiving,
+/// opening and clicking through issues while subscribed.
+///
+/// Refuses to touch a database that already has subscribers, which keeps it
+/// away from production and makes every run with a given seed identical.
+pub(super) async fn run(
+    args: SeedArgs,
+    context: &Context<'_>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(args.days > 0, "`--days` must be at least 1");
+    let until = args
+        .until
+        .unwrap_or_else(|| Utc::now().date().and_hms(0, 0, 0));
+    let mut generator = Generator::new(args.seed, until - Duration::days(args.days), until);
+    let issues = generator.issues(args.issues);
+
+    let mut transaction = context.pool.begin().await?;
+    let existing = sqlx::query!(r#"SELECT EXISTS (SELECT 1 FROM subscriptions) AS "exists!""#)
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to check for existing subscribers")?
+        .exists;
+    anyhow::ensure!(
+        !existing,
+        "The database already has subscribers: seed an empty one instead"
+    );
+
+    let mut seeded = Seeded {
+        seed: args.seed,
+        issues: issues.len(),
+        ..Seeded::default()
+    };
+    insert_issues(&mut transaction, &issues)
+        .await
+        .context("Failed to insert issues")?;
+    let mut created = 0;
+    while created < args.subscribers {
+        let batch_size = BATCH_SIZE.min(args.subscribers - created);
+        let subscribers: Vec<_> = (created..created + batch_size)
+            .map(|index| generator.subscriber(index, &issues))
+            .collect();
+        let history = generator.history(&subscribers, &issues);
+        insert_subscribers(&mut transaction, &subscribers)
+            .await
+            .context("Failed to insert subscribers")?;
+        insert_history(&mut transaction, &history)
+            .await
+            .context("Failed to insert the delivery history")?;
+
+        created += batch_size;
+        seeded.subscribers += subscribers.len();
+        seeded.confirmed += subscribers
+            .iter()
+            .filter(|s| s.status == "confirmed")
+            .count();
+        seeded.deliveries += history.deliveries.len();
+        seeded.opens += history.opens.len();
+        seeded.clicks += history.clicks.len();
+    }
+    record_audit_event(
+        &mut transaction,
+        &context.actor,
+        "database.seeded",
+        None,
+        serde_json::to_value(&seeded)?,
+    )
+    .await
+    .context("Failed to record the seeding in the audit log")?;
+    transaction.commit().await?;
+
+    print_record(out, context.format, &seeded)
+}
+
+struct SeededIssue {
+    id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+    track_opens: bool,
+    track_clicks: bool,
+    links: Vec<String>,
+}
+
+struct SeededSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    token: String,
+    status: &'static str,
+    subscribed_at: DateTime<Utc>,
+    confirmed_at: Option<DateTime<Utc>>,
+    /// When they unsubscribed or bounced.
+    left_at: Option<DateTime<Utc>>,
+    /// How likely they are to open an issue, and click through.
+    engagement: f64,
+}
+
+impl SeededSubscriber {
+    /// Whether `issue` went out while they were subscribed.
+    fn received(&self, issue: &SeededIssue) -> bool {
+        self.confirmed_at
+            .is_some_and(|confirmed_at| confirmed_at < issue.published_at)
+            && self
+                .left_at
+                .is_none_or(|left_at| issue.published_at < left_at)
+    }
+}
+
+struct SeededDelivery {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    status: &'static str,
+    provider_message_id: Option<String>,
+    failure_reason: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+struct SeededOpen {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    opened_at: DateTime<Utc>,
+    user_agent: &'static str,
+}
+
+struct SeededClick {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: String,
+    clicked_at: DateTime<Utc>,
+    user_agent: &'static str,
+}
+
+#[derive(Default)]
+struct SeededHistory {
+    deliveries: Vec<SeededDelivery>,
+    opens: Vec<SeededOpen>,
+    clicks: Vec<SeededClick>,
+}
+
+/// Generates the data. Everything comes out of a single seeded generator,
+/// in a fixed order, so the output only depends on the seed and on what is
+/// asked for.
+struct Generator {
+    rng: StdRng,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+}
+
+impl Generator {
+    fn new(seed: u64, from: DateTime<Utc>, until: DateTime<Utc>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            from,
+            until,
+        }
+    }
+
+    /// Issues spread evenly over the period, give or take a day, oldest
+    /// first.
+    fn issues(&mut self, count: usize) -> Vec<SeededIssue> {
+        let period = (self.until - self.from).num_seconds();
+        (0..count)
+            .map(|index| {
+                let slot = period * (index as i64 + 1) / (count as i64 + 1);
+                let jitter = self.rng.gen_range(-43_200, 43_200);
+                let published_at =
+                    (self.from + Duration::seconds(slot + jitter)).clamp(self.from, self.until);
+                let title: String = Sentence(3..8).fake_with_rng(&mut self.rng);
+                let links: Vec<String> = (0..self.rng.gen_range(1, 4))
+                    .map(|_| {
+                        let words: Vec<String> = Words(2..5).fake_with_rng(&mut self.rng);
+                        format!("https://example.com/articles/{}", words.join("-"))
+                    })
+                    .collect();
+                let paragraphs: Vec<String> = (0..self.rng.gen_range(2, 6))
+                    .map(|_| Paragraph(3..7).fake_with_rng(&mut self.rng))
+                    .collect();
+                let html_links: String = links
+                    .iter()
+                    .map(|link| format!(r#"<p><a href="{0}">{0}</a></p>"#, link))
+                    .collect();
+                SeededIssue {
+                    id: self.uuid(),
+                    text_content: format!("{}\n\n{}", paragraphs.join("\n\n"), links.join("\n")),
+                    html_content: format!(
+                        "<h1>{}</h1><p>{}</p>{}",
+                        title,
+                        paragraphs.join("</p><p>"),
+                        html_links
+                    ),
+                    title,
+                    published_at,
+                    track_opens: self.rng.gen_bool(0.9),
+                    track_clicks: self.rng.gen_bool(0.8),
+                    links,
+                }
+            })
+            .collect()
+    }
+
+    /// The `index`-th subscriber, whose email is unique because of it.
+    fn subscriber(&mut self, index: usize, issues: &[SeededIssue]) -> SeededSubscriber {
+        let first_name: String = FirstName().fake_with_rng(&mut self.rng);
+        let last_name: String = LastName().fake_with_rng(&mut self.rng);
+        let domain = EMAIL_DOMAINS[self.rng.gen_range(0, EMAIL_DOMAINS.len())];
+        let email = format!(
+            "{}.{}.{}@{}",
+            email_local_part(&first_name),
+            email_local_part(&last_name),
+            index,
+            domain
+        );
+        // Sign-ups pick up over time: the density grows linearly towards
+        // the end of the period.
+        let period = (self.until - self.from).num_seconds() as f64;
+        let subscribed_at =
+            self.from + Duration::seconds((period * self.rng.gen::<f64>().sqrt()) as i64);
+        let status = pick(&mut self.rng, STATUS_SHARES);
+
+        let confirmed_at = (status != "pending_confirmation").then(|| {
+            // Most confirm within the hour, a few days later.
+            let delay = self.exponential_delay(Duration::minutes(40));
+            (subscribed_at + delay).min(self.until)
+        });
+        let left_at = match (status, confirmed_at) {
+            ("unsubscribed", Some(confirmed_at)) => Some(self.between(confirmed_at, self.until)),
+            // A bounce is the answer to an issue.
+            ("bounced", Some(confirmed_at)) => {
+                let received: Vec<_> = issues
+                    .iter()
+                    .filter(|i| confirmed_at < i.published_at)
+                    .collect();
+                Some(if received.is_empty() {
+                    self.between(confirmed_at, self.until)
+                } else {
+                    let issue = received[self.rng.gen_range(0, received.len())];
+                    issue.published_at + Duration::seconds(self.rng.gen_range(1, 600))
+                })
+            }
+            _ => None,
+        };
+
+        SeededSubscriber {
+            id: self.uuid(),
+            email,
+            name: format!("{} {}", first_name, last_name),
+            token: (0..25).map(|_| self.rng.sample(Alphanumeric)).collect(),
+            status,
+            subscribed_at,
+            confirmed_at,
+            left_at,
+            engagement: self.rng.gen::<f64>(),
+        }
+    }
+
+    /// What happened to the issues sent to `subscribers`.
+    fn history(
+        &mut self,
+        subscribers: &[SeededSubscriber],
+        issues: &[SeededIssue],
+    ) -> SeededHistory {
+        let mut history = SeededHistory::default();
+        for subscriber in subscribers {
+            let received: Vec<_> = issues.iter().filter(|i| subscriber.received(i)).collect();
+            for (position, issue) in received.iter().enumerate() {
+                let bounced = subscriber.status == "bounced" && position + 1 == received.len();
+                let status = if bounced {
+                    "bounced"
+                } else {
+                    pick(&mut self.rng, DELIVERY_SHARES)
+                };
+                let created_at = issue.published_at;
+                let updated_at =
+                    (created_at + Duration::seconds(self.rng.gen_range(1, 300))).min(self.until);
+                history.deliveries.push(SeededDelivery {
+                    newsletter_issue_id: issue.id,
+                    subscriber_id: subscriber.id,
+                    subscriber_email: subscriber.email.clone(),
+                    status,
+                    provider_message_id: (status != "failed")
+                        .then(|| format!("<{}@smtp-relay.mailin.fr>", self.uuid().to_simple())),
+                    failure_reason: (status == "failed")
+                        .then(|| "Email provider returned 500 Internal Server Error".into()),
+                    created_at,
+                    updated_at,
+                });
+
+                if status != "delivered" || !issue.track_opens {
+                    continue;
+                }
+                if !self.rng.gen_bool(0.7 * subscriber.engagement) {
+                    continue;
+                }
+                // Opens trail off over the following days.
+                let opened_at =
+                    (updated_at + self.exponential_delay(Duration::hours(8))).min(self.until);
+                let user_agent = USER_AGENTS[self.rng.gen_range(0, USER_AGENTS.len())];
+                history.opens.push(SeededOpen {
+                    newsletter_issue_id: issue.id,
+                    subscriber_id: subscriber.id,
+                    opened_at,
+                    user_agent,
+                });
+                if issue.track_clicks && self.rng.gen_bool(0.4 * subscriber.engagement) {
+                    let url = issue.links[self.rng.gen_range(0, issue.links.len())].clone();
+                    history.clicks.push(SeededClick {
+                        newsletter_issue_id: issue.id,
+                        subscriber_id: subscriber.id,
+                        url,
+                        clicked_at: (opened_at + Duration::seconds(self.rng.gen_range(5, 300)))
+                            .min(self.until),
+                        user_agent,
+                    });
+                }
+            }
+        }
+        history
+    }
+
+    fn uuid(&mut self) -> Uuid {
+        uuid::Builder::from_bytes(self.rng.gen())
+            .set_variant(uuid::Variant::RFC4122)
+            .set_version(uuid::Version::Random)
+            .build()
+    }
+
+    fn between(&mut self, from: DateTime<Utc>, until: DateTime<Utc>) -> DateTime<Utc> {
+        let seconds = (until - from).num_seconds();
+        if seconds <= 0 {
+            return from;
+        }
+        from + Duration::seconds(self.rng.gen_range(0, seconds))
+    }
+
+    /// Exponentially distributed, around `mean`.
+    fn exponential_delay(&mut self, mean: Duration) -> Duration {
+        let factor = -(1.0 - self.rng.gen::<f64>()).ln();
+        Duration::seconds((mean.num_seconds() as f64 * factor) as i64)
+    }
+}
+
+/// Picks a value with a probability proportional to its share.
+fn pick(rng: &mut StdRng, shares: &[(&'static str, u32)]) -> &'static str {
+    let total: u32 = shares.iter().map(|(_, share)| share).sum();
+    let mut roll = rng.gen_range(0, total);
+    for (value, share) in shares {
+        if roll < *share {
+            return value;
+        }
+        roll -= share;
+    }
+    unreachable!("The roll is below the total of the shares")
+}
+
+/// Names such as "O'Kon" lose what an email address would not have.
+fn email_local_part(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[tracing::instrument(name = "Insert seeded issues", skip_all, fields(issues = issues.len()))]
+async fn insert_issues(
+    transaction: &mut Transaction<'_, Postgres>,
+    issues: &[SeededIssue],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = issues.iter().map(|i| i.id).collect();
+    let titles: Vec<String> = issues.iter().map(|i| i.title.clone()).collect();
+    let text_contents: Vec<String> = issues.iter().map(|i| i.text_content.clone()).collect();
+    let html_contents: Vec<String> = issues.iter().map(|i| i.html_content.clone()).collect();
+    let published_at: Vec<DateTime<Utc>> = issues.iter().map(|i| i.published_at).collect();
+    let track_opens: Vec<bool> = issues.iter().map(|i| i.track_opens).collect();
+    let track_clicks: Vec<bool> = issues.iter().map(|i| i.track_clicks).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at,
+            track_opens, track_clicks
+        )
+        SELECT * FROM UNNEST(
+            $1::uuid[], $2::text[], $3::text[], $4::text[], $5::timestamptz[], $6::bool[],
+            $7::bool[]
+        )
+        "#,
+        &ids,
+        &titles,
+        &text_contents,
+        &html_contents,
+        &published_at,
+        &track_opens,
+        &track_clicks,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Insert seeded subscribers",
+    skip_all,
+    fields(subscribers = subscribers.len())
+)]
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscribers: &[SeededSubscriber],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = subscribers.iter().map(|s| s.id).collect();
+    let emails: Vec<String> = subscribers.iter().map(|s| s.email.clone()).collect();
+    let email_hashes: Vec<String> = subscribers.iter().map(|s| email_hash(&s.email)).collect();
+    let names: Vec<String> = subscribers.iter().map(|s| s.name.clone()).collect();
+    let subscribed_at: Vec<DateTime<Utc>> = subscribers.iter().map(|s| s.subscribed_at).collect();
+    let statuses: Vec<String> = subscribers.iter().map(|s| s.status.to_owned()).collect();
+    let tokens: Vec<String> = subscribers.iter().map(|s| s.token.clone()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_hash, name, subscribed_at, status)
+        SELECT * FROM UNNEST(
+            $1::uuid[], $2::text[], $3::text[], $4::text[], $5::timestamptz[], $6::text[]
+        )
+        "#,
+        &ids,
+        &emails,
+        &email_hashes,
+        &names,
+        &subscribed_at,
+        &statuses,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        &tokens,
+        &ids,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    // Each subscriber's path to their status, as the application would
+    // have recorded it.
+    let mut change_ids = Vec::new();
+    let mut from_statuses = Vec::new();
+    let mut to_statuses = Vec::new();
+    let mut changed_at = Vec::new();
+    for subscriber in subscribers {
+        let mut steps = vec![("", "pending_confirmation", subscriber.subscribed_at)];
+        if let Some(confirmed_at) = subscriber.confirmed_at {
+            steps.push(("pending_confirmation", "confirmed", confirmed_at));
+        }
+        if let Some(left_at) = subscriber.left_at {
+            steps.push(("confirmed", subscriber.status, left_at));
+        }
+        for (from, to, at) in steps {
+            change_ids.push(subscriber.id);
+            from_statuses.push(from.to_owned());
+            to_statuses.push(to.to_owned());
+            changed_at.push(at);
+        }
+    }
+    // Arrays cannot carry NULLs through sqlx, so the missing first status
+    // travels as ''.
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+        SELECT t.subscriber_id, NULLIF(t.from_status, ''), t.to_status, t.changed_at
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::timestamptz[])
+            AS t(subscriber_id, from_status, to_status, changed_at)
+        "#,
+        &change_ids,
+        &from_statuses,
+        &to_statuses,
+        &changed_at,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    // Hard bounces are suppressed, as the webhook would have done.
+    let bounced: Vec<String> = subscribers
+        .iter()
+        .filter(|s| s.status == "bounced")
+        .map(|s| email_hash(&s.email))
+        .collect();
+    let bounced_at: Vec<DateTime<Utc>> = subscribers
+        .iter()
+        .filter(|s| s.status == "bounced")
+        .map(|s| s.left_at.unwrap_or(s.subscribed_at))
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_hash, reason, source, created_at)
+        SELECT t.email_hash, 'bounce', 'seed', t.created_at
+        FROM UNNEST($1::text[], $2::timestamptz[]) AS t(email_hash, created_at)
+        "#,
+        &bounced,
+        &bounced_at,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Insert seeded delivery history",
+    skip_all,
+    fields(deliveries = history.deliveries.len())
+)]
+async fn insert_history(
+    transaction: &mut Transaction<'_, Postgres>,
+    history: &SeededHistory,
+) -> Result<(), sqlx::Error> {
+    let deliveries = &history.deliveries;
+    let issue_ids: Vec<Uuid> = deliveries.iter().map(|d| d.newsletter_issue_id).collect();
+    let subscriber_ids: Vec<Uuid> = deliveries.iter().map(|d| d.subscriber_id).collect();
+    let emails: Vec<String> = deliveries
+        .iter()
+        .map(|d| d.subscriber_email.clone())
+        .collect();
+    let statuses: Vec<String> = deliveries.iter().map(|d| d.status.to_owned()).collect();
+    let provider_message_ids: Vec<String> = deliveries
+        .iter()
+        .map(|d| d.provider_message_id.clone().unwrap_or_default())
+        .collect();
+    let failure_reasons: Vec<String> = deliveries
+        .iter()
+        .map(|d| d.failure_reason.clone().unwrap_or_default())
+        .collect();
+    let created_at: Vec<DateTime<Utc>> = deliveries.iter().map(|d| d.created_at).collect();
+    let updated_at: Vec<DateTime<Utc>> = deliveries.iter().map(|d| d.updated_at).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_id, subscriber_email, status,
+            provider_message_id, failure_reason, created_at, updated_at
+        )
+        SELECT t.newsletter_issue_id, t.subscriber_id, t.subscriber_email, t.status,
+            NULLIF(t.provider_message_id, ''), NULLIF(t.failure_reason, ''), t.created_at,
+            t.updated_at
+        FROM UNNEST(
+            $1::uuid[], $2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[],
+            $7::timestamptz[], $8::timestamptz[]
+        ) AS t(
+            newsletter_issue_id, subscriber_id, subscriber_email, status,
+            provider_message_id, failure_reason, created_at, updated_at
+        )
+        "#,
+        &issue_ids,
+        &subscriber_ids,
+        &emails,
+        &statuses,
+        &provider_message_ids,
+        &failure_reasons,
+        &created_at,
+        &updated_at,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let opens = &history.opens;
+    let issue_ids: Vec<Uuid> = opens.iter().map(|o| o.newsletter_issue_id).collect();
+    let subscriber_ids: Vec<Uuid> = opens.iter().map(|o| o.subscriber_id).collect();
+    let opened_at: Vec<DateTime<Utc>> = opens.iter().map(|o| o.opened_at).collect();
+    let user_agents: Vec<String> = opens.iter().map(|o| o.user_agent.to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO open_events (newsletter_issue_id, subscriber_id, opened_at, user_agent)
+        SELECT * FROM UNNEST($1::uuid[], $2::uuid[], $3::timestamptz[], $4::text[])
+        "#,
+        &issue_ids,
+        &subscriber_ids,
+        &opened_at,
+        &user_agents,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let clicks = &history.clicks;
+    let issue_ids: Vec<Uuid> = clicks.iter().map(|c| c.newsletter_issue_id).collect();
+    let subscriber_ids: Vec<Uuid> = clicks.iter().map(|c| c.subscriber_id).collect();
+    let urls: Vec<String> = clicks.iter().map(|c| c.url.clone()).collect();
+    let clicked_at: Vec<DateTime<Utc>> = clicks.iter().map(|c| c.clicked_at).collect();
+    let user_agents: Vec<String> = clicks.iter().map(|c| c.user_agent.to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO click_events (newsletter_issue_id, subscriber_id, url, clicked_at, user_agent)
+        SELECT * FROM UNNEST($1::uuid[], $2::uuid[], $3::text[], $4::timestamptz[], $5::text[])
+        "#,
+        &issue_ids,
+        &subscriber_ids,
+        &urls,
+        &clicked_at,
+        &user_agents,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Generator, SeededIssue, SeededSubscriber};
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn generate(seed: u64) -> (Generator, Vec<SeededIssue>, Vec<SeededSubscriber>) {
+        let until = Utc.ymd(2022, 10, 1).and_hms(0, 0, 0);
+        let mut generator = Generator::new(seed, until - Duration::days(365), until);
+        let issues = generator.issues(10);
+        let subscribers = (0..500)
+            .map(|index| generator.subscriber(index, &issues))
+            .collect();
+        (generator, issues, subscribers)
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_data() {
+        let (_, first_issues, first) = generate(7);
+        let (_, second_issues, second) = generate(7);
+        let (_, _, other) = generate(8);
+
+        let emails = |subscribers: &[SeededSubscriber]| -> Vec<String> {
+            subscribers.iter().map(|s| s.email.clone()).collect()
+        };
+        assert_eq!(emails(&first), emails(&second));
+        assert_ne!(emails(&first), emails(&other));
+        assert_eq!(first_issues[3].id, second_issues[3].id);
+        assert_eq!(first[42].subscribed_at, second[42].subscribed_at);
+    }
+
+    #[test]
+    fn every_status_is_represented() {
+        let (_, _, subscribers) = generate(0);
+
+        for status in [
+            "confirmed",
+            "pending_confirmation",
+            "unsubscribed",
+            "bounced",
+        ] {
+            assert!(
+                subscribers.iter().any(|s| s.status == status),
+                "No {} subscriber",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn issues_are_only_delivered_while_subscribed() {
+        let (mut generator, issues, subscribers) = generate(0);
+
+        let history = generator.history(&subscribers, &issues);
+
+        for delivery in &history.deliveries {
+            let subscriber = subscribers
+                .iter()
+                .find(|s| s.id == delivery.subscriber_id)
+                .unwrap();
+            let issue = issues
+                .iter()
+                .find(|i| i.id == delivery.newsletter_issue_id)
+                .unwrap();
+            assert!(subscriber.confirmed_at.unwrap() < issue.published_at);
+            assert!(subscriber.left_at.is_none_or(|l| issue.published_at < l));
+        }
+        assert!(subscribers
+            .iter()
+            .filter(|s| s.status == "pending_confirmation")
+            .all(|s| history.deliveries.iter().all(|d| d.subscriber_id != s.id)));
+        assert!(!history.opens.is_empty());
+    }
+}
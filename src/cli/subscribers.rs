@@ -0,0 +1,310 @@
+use super::output::{optional, print_record, print_records, timestamp, Record};
+use super::Context;
+use crate::audit::record_audit_event;
+use crate::erasure::{anonymise_subscriber, erase_subscriber, Erasure};
+use crate::subscription_status::change_status;
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::io::Write;
+use uuid::Uuid;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SubscribersCommand {
+    /// Lists subscribers, newest first.
+    List {
+        /// Only subscribers in this status, e.g. `confirmed`.
+        #[clap(long)]
+        status: Option<String>,
+        #[clap(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Shows a subscriber, given their id or email.
+    Show { subscriber: String },
+    /// Confirms a subscriber without them following the confirmation link.
+    Confirm { subscriber: String },
+    /// Stops sending issues to a subscriber.
+    Unsubscribe { subscriber: String },
+    /// Deletes a subscriber and their confirmation tokens, anonymising their
+    /// deliveries and tracking events as `erase` does, without suppressing
+    /// their address.
+    Delete { subscriber: String },
+    /// Erases a subscriber, keeping their deliveries and tracking events
+    /// anonymised for issue stats, and suppresses their address.
+    Erase { subscriber: String },
+}
+
+#[derive(serde::Serialize)]
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl Record for Subscriber {
+    const COLUMNS: &'static [&'static str] = &["id", "email", "name", "status", "subscribed_at"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            timestamp(&self.subscribed_at),
+        ]
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberDetails {
+    #[serde(flatten)]
+    subscriber: Subscriber,
+    status_changed_at: Option<DateTime<Utc>>,
+    /// Issues sent, or attempted, to the subscriber.
+    deliveries: i64,
+}
+
+impl Record for SubscriberDetails {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "email",
+        "name",
+        "status",
+        "subscribed_at",
+        "status_changed_at",
+        "deliveries",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        let mut cells = self.subscriber.cells();
+        cells.push(optional(&self.status_changed_at.as_ref().map(timestamp)));
+        cells.push(self.deliveries.to_string());
+        cells
+    }
+}
+
+#[derive(serde::Serialize)]
+struct StatusChange {
+    id: Uuid,
+    status: &'static str,
+    /// `false` if the subscriber was already in `status`.
+    changed: bool,
+}
+
+impl Record for StatusChange {
+    const COLUMNS: &'static [&'static str] = &["id", "status", "changed"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.status.into(),
+            self.changed.to_string(),
+        ]
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Deletion {
+    id: Uuid,
+    deleted: bool,
+    anonymised_deliveries: u64,
+}
+
+impl Record for Deletion {
+    const COLUMNS: &'static [&'static str] = &["id", "deleted", "anonymised_deliveries"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.deleted.to_string(),
+            self.anonymised_deliveries.to_string(),
+        ]
+    }
+}
+
+impl Record for Erasure {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "anonymised_deliveries",
+        "anonymised_opens",
+        "anonymised_clicks",
+        "anonymised_email_events",
+        "suppressed",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.anonymised_deliveries.to_string(),
+            self.anonymised_opens.to_string(),
+            self.anonymised_clicks.to_string(),
+            self.anonymised_email_events.to_string(),
+            self.suppressed.to_string(),
+        ]
+    }
+}
+
+pub(super) async fn run(
+    command: SubscribersCommand,
+    context: &Context<'_>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let pool = &context.pool;
+    match command {
+        SubscribersCommand::List { status, limit } => {
+            let subscribers = sqlx::query_as!(
+                Subscriber,
+                r#"
+                SELECT id, email, name, status, subscribed_at
+                FROM subscriptions
+                WHERE $1::text IS NULL OR status = $1
+                ORDER BY subscribed_at DESC, id
+                LIMIT $2
+                "#,
+                status,
+                limit,
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to list subscribers")?;
+            print_records(out, context.format, &subscribers)
+        }
+        SubscribersCommand::Show { subscriber } => {
+            let subscriber = find_subscriber(pool, &subscriber).await?;
+            let details = sqlx::query!(
+                r#"
+                SELECT
+                    (SELECT MAX(changed_at) FROM subscription_status_changes
+                        WHERE subscriber_id = $1) AS status_changed_at,
+                    (SELECT COUNT(*) FROM issue_deliveries
+                        WHERE subscriber_id = $1) AS "deliveries!"
+                "#,
+                subscriber.id
+            )
+            .fetch_one(pool)
+            .await
+            .context("Failed to fetch the subscriber's history")?;
+            print_record(
+                out,
+                context.format,
+                &SubscriberDetails {
+                    subscriber,
+                    status_changed_at: details.status_changed_at,
+                    deliveries: details.deliveries,
+                },
+            )
+        }
+        SubscribersCommand::Confirm { subscriber } => {
+            let change = set_status(context, &subscriber, "confirmed").await?;
+            print_record(out, context.format, &change)
+        }
+        SubscribersCommand::Unsubscribe { subscriber } => {
+            let change = set_status(context, &subscriber, "unsubscribed").await?;
+            print_record(out, context.format, &change)
+        }
+        SubscribersCommand::Delete { subscriber } => {
+            let subscriber = find_subscriber(pool, &subscriber).await?;
+            let mut transaction = pool.begin().await?;
+            let anonymised =
+                anonymise_subscriber(&mut transaction, subscriber.id, &subscriber.email)
+                    .await
+                    .context("Failed to anonymise the subscriber's deliveries and events")?;
+            // Their tokens, status history and export links go with them, by
+            // cascade.
+            let deleted = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber.id)
+                .execute(&mut transaction)
+                .await
+                .context("Failed to delete the subscriber")?
+                .rows_affected()
+                == 1;
+            if deleted {
+                record_audit_event(
+                    &mut transaction,
+                    &context.actor,
+                    "subscriber.deleted",
+                    Some(&subscriber.id.to_string()),
+                    serde_json::json!({
+                        "status": subscriber.status,
+                        "anonymised_deliveries": anonymised.deliveries,
+                        "anonymised_opens": anonymised.opens,
+                        "anonymised_clicks": anonymised.clicks,
+                        "anonymised_email_events": anonymised.email_events,
+                    }),
+                )
+                .await
+                .context("Failed to record the deletion in the audit log")?;
+            }
+            transaction.commit().await?;
+            print_record(
+                out,
+                context.format,
+                &Deletion {
+                    id: subscriber.id,
+                    deleted,
+                    anonymised_deliveries: anonymised.deliveries,
+                },
+            )
+        }
+        SubscribersCommand::Erase { subscriber } => {
+            let subscriber = find_subscriber(pool, &subscriber).await?;
+            let mut transaction = pool.begin().await?;
+            let erasure = erase_subscriber(&mut transaction, &context.actor, subscriber.id)
+                .await
+                .context("Failed to erase the subscriber")?
+                .context("The subscriber was deleted in the meantime")?;
+            transaction.commit().await?;
+            print_record(out, context.format, &erasure)
+        }
+    }
+}
+
+/// Moves a subscriber to `status`, auditing the change if there is one.
+async fn set_status(
+    context: &Context<'_>,
+    subscriber: &str,
+    status: &'static str,
+) -> anyhow::Result<StatusChange> {
+    let subscriber = find_subscriber(&context.pool, subscriber).await?;
+    let mut transaction = context.pool.begin().await?;
+    let changed = change_status(&mut transaction, subscriber.id, status)
+        .await
+        .context("Failed to change the subscription status")?;
+    if changed {
+        record_audit_event(
+            &mut transaction,
+            &context.actor,
+            &format!("subscriber.{}", status),
+            Some(&subscriber.id.to_string()),
+            serde_json::json!({ "from_status": subscriber.status }),
+        )
+        .await
+        .context("Failed to record the status change in the audit log")?;
+    }
+    transaction.commit().await?;
+    Ok(StatusChange {
+        id: subscriber.id,
+        status,
+        changed,
+    })
+}
+
+/// Looks a subscriber up by id or, failing that, by email.
+async fn find_subscriber(pool: &PgPool, subscriber: &str) -> anyhow::Result<Subscriber> {
+    let id = Uuid::parse_str(subscriber).ok();
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1 OR email = $2
+        "#,
+        id,
+        subscriber,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look the subscriber up")?
+    .with_context(|| format!("No subscriber has the id or email `{}`", subscriber))
+}
@@ -0,0 +1,122 @@
+use super::output::{optional, print_record, Record};
+use super::Context;
+use crate::audit::record_audit_event;
+use crate::users::{change_password, compute_password_hash, create_user, generate_password};
+use anyhow::Context as _;
+use secrecy::{ExposeSecret, Secret};
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum UsersCommand {
+    /// Creates an admin user. A password is generated, and printed once,
+    /// unless given on stdin.
+    Create {
+        username: String,
+        /// Read the password from the first line of stdin.
+        #[clap(long)]
+        password_stdin: bool,
+    },
+    /// Sets a new password for a user, generated unless given on stdin.
+    ResetPassword {
+        username: String,
+        /// Read the password from the first line of stdin.
+        #[clap(long)]
+        password_stdin: bool,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct Credentials {
+    user_id: Uuid,
+    username: String,
+    /// Only set when generated: it cannot be recovered later.
+    password: Option<String>,
+}
+
+impl Record for Credentials {
+    const COLUMNS: &'static [&'static str] = &["user_id", "username", "password"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.user_id.to_string(),
+            self.username.clone(),
+            optional(&self.password),
+        ]
+    }
+}
+
+pub(super) async fn run(
+    command: UsersCommand,
+    context: &Context<'_>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let (username, password_stdin, is_new) = match command {
+        UsersCommand::Create {
+            username,
+            password_stdin,
+        } => (username, password_stdin, true),
+        UsersCommand::ResetPassword {
+            username,
+            password_stdin,
+        } => (username, password_stdin, false),
+    };
+    let (password, generated) = if password_stdin {
+        (read_password()?, false)
+    } else {
+        (generate_password(), true)
+    };
+    // Hashing is deliberately slow.
+    let (password_hash, password) = tokio::task::spawn_blocking(move || {
+        let password_hash = compute_password_hash(&password);
+        (password_hash, password)
+    })
+    .await?;
+    let password_hash = password_hash.context("Failed to hash the password")?;
+
+    let mut transaction = context.pool.begin().await?;
+    let (user_id, action) = if is_new {
+        let user_id = create_user(&mut transaction, &username, &password_hash)
+            .await
+            .context("Failed to create the user")?
+            .with_context(|| format!("The username `{}` is taken", username))?;
+        (user_id, "user.created")
+    } else {
+        let user_id = change_password(&mut transaction, &username, &password_hash)
+            .await
+            .context("Failed to change the password")?
+            .with_context(|| format!("There is no user `{}`", username))?;
+        (user_id, "user.password_reset")
+    };
+    record_audit_event(
+        &mut transaction,
+        &context.actor,
+        action,
+        Some(&user_id.to_string()),
+        serde_json::json!({ "username": username }),
+    )
+    .await
+    .context("Failed to record the change in the audit log")?;
+    transaction.commit().await?;
+
+    print_record(
+        out,
+        context.format,
+        &Credentials {
+            user_id,
+            username,
+            password: generated.then(|| password.expose_secret().clone()),
+        },
+    )
+}
+
+fn read_password() -> anyhow::Result<Secret<String>> {
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Failed to read the password from stdin")?;
+    let password = password.trim_end_matches(['\r', '\n']).to_owned();
+    anyhow::ensure!(!password.is_empty(), "The password must not be empty");
+    Ok(Secret::new(password))
+}
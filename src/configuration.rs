@@ -1,14 +1,259 @@
 use crate::domain::SubscriberEmail;
+use crate::helper::error_chain_fmt;
+use crate::rate_limiter::RateLimiter;
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Prefix of the development secrets shipped in `base.yaml`. They are
+/// public, so production must replace every one of them.
+const PLACEHOLDER_SECRET_PREFIX: &str = "local-";
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub webhooks: WebhookSettings,
+    pub admin: AdminSettings,
+    pub tracking: TrackingSettings,
+    pub health: HealthSettings,
+    pub telemetry: TelemetrySettings,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("Failed to load the configuration")]
+    LoadError(#[from] config::ConfigError),
+    #[error("{0}")]
+    EnvironmentError(String),
+    #[error("The configuration is invalid:{}", bullet_list(.0))]
+    ValidationError(Vec<String>),
+    #[error("Some configuration values could not be resolved:{}", bullet_list(.0))]
+    ReferenceError(Vec<String>),
+}
+
+fn bullet_list(items: &[String]) -> String {
+    items.iter().map(|item| format!("\n  - {}", item)).collect()
+}
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl Settings {
+    /// Checks every setting up front, so that mistakes are reported all at
+    /// once at startup rather than one by one when first used.
+    pub fn validate(&self, environment: &Environment) -> Result<(), ConfigurationError> {
+        let mut problems = Vec::new();
+
+        let database = &self.database;
+        check_not_empty(&mut problems, "database.host", &database.host);
+        check_not_empty(&mut problems, "database.username", &database.username);
+        check_not_empty(
+            &mut problems,
+            "database.database_name",
+            &database.database_name,
+        );
+        if environment.is_production() && !database.required_ssl {
+            problems.push("database.required_ssl must be true in production.".into());
+        }
+
+        let application = &self.application;
+        check_not_empty(&mut problems, "application.host", &application.host);
+        check_url(&mut problems, "application.base_url", &application.base_url);
+        if application.metrics_port == Some(application.port) {
+            problems.push("application.metrics_port must differ from application.port.".into());
+        }
+        if environment.is_production() && application.metrics_port.is_none() {
+            problems.push(
+                "application.metrics_port must be set in production, to keep /metrics off the \
+                public port."
+                    .into(),
+            );
+        }
+        check_range(
+            &mut problems,
+            "application.shutdown_timeout_seconds",
+            application.shutdown_timeout_seconds,
+            1..=300,
+        );
+
+        let email_client = &self.email_client;
+        check_url(
+            &mut problems,
+            "email_client.base_url",
+            &email_client.base_url,
+        );
+        if let Err(e) = email_client.sender() {
+            problems.push(format!("email_client.sender is invalid: {}", e));
+        }
+        check_not_empty(
+            &mut problems,
+            "email_client.auth_token",
+            email_client.auth_token.expose_secret(),
+        );
+        check_range(
+            &mut problems,
+            "email_client.timeout_milliseconds",
+            email_client.timeout_milliseconds,
+            100..=120_000,
+        );
+        check_range(
+            &mut problems,
+            "email_client.max_concurrent_requests",
+            email_client.max_concurrent_requests as u64,
+            1..=1_000,
+        );
+        check_range(
+            &mut problems,
+            "email_client.max_retries",
+            email_client.max_retries.into(),
+            0..=10,
+        );
+        // The provider accepts at most 1000 message versions per batch.
+        check_range(
+            &mut problems,
+            "email_client.batch_size",
+            email_client.batch_size as u64,
+            1..=1_000,
+        );
+        for (field, limit) in [
+            (
+                "email_client.rate_limit_per_second",
+                email_client.rate_limit_per_second,
+            ),
+            (
+                "email_client.rate_limit_per_hour",
+                email_client.rate_limit_per_hour,
+            ),
+        ] {
+            if limit == Some(0) {
+                problems.push(format!("{} must be at least 1 when set.", field));
+            }
+        }
+
+        check_not_empty(
+            &mut problems,
+            "webhooks.shared_secret",
+            self.webhooks.shared_secret.expose_secret(),
+        );
+        check_not_empty(
+            &mut problems,
+            "admin.api_token",
+            self.admin.api_token.expose_secret(),
+        );
+
+        let tracking = &self.tracking;
+        check_not_empty(
+            &mut problems,
+            "tracking.signing_key",
+            tracking.signing_key.expose_secret(),
+        );
+        check_range(
+            &mut problems,
+            "tracking.flush_interval_milliseconds",
+            tracking.flush_interval_milliseconds,
+            1..=3_600_000,
+        );
+        check_range(
+            &mut problems,
+            "tracking.buffer_capacity",
+            tracking.buffer_capacity as u64,
+            1..=1_000_000,
+        );
+
+        check_range(
+            &mut problems,
+            "health.check_timeout_milliseconds",
+            self.health.check_timeout_milliseconds,
+            100..=60_000,
+        );
+
+        let telemetry = &self.telemetry;
+        check_not_empty(
+            &mut problems,
+            "telemetry.service_name",
+            &telemetry.service_name,
+        );
+        if let Some(otlp_endpoint) = &telemetry.otlp_endpoint {
+            check_url(&mut problems, "telemetry.otlp_endpoint", otlp_endpoint);
+        }
+        if !(0.0..=1.0).contains(&telemetry.sampling_ratio) {
+            problems.push(format!(
+                "telemetry.sampling_ratio must be between 0 and 1, got {}.",
+                telemetry.sampling_ratio
+            ));
+        }
+
+        if environment.is_production() {
+            for (field, secret) in [
+                ("email_client.auth_token", &email_client.auth_token),
+                ("webhooks.shared_secret", &self.webhooks.shared_secret),
+                ("admin.api_token", &self.admin.api_token),
+                ("tracking.signing_key", &tracking.signing_key),
+            ] {
+                if secret
+                    .expose_secret()
+                    .starts_with(PLACEHOLDER_SECRET_PREFIX)
+                {
+                    problems.push(format!(
+                        "{} must be set in production: the value in base.yaml is a public \
+                        placeholder.",
+                        field
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigurationError::ValidationError(problems))
+        }
+    }
+}
+
+fn check_not_empty(problems: &mut Vec<String>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        problems.push(format!("{} must not be empty.", field));
+    }
+}
+
+/// Accepts absolute `http` and `https` URLs.
+fn check_url(problems: &mut Vec<String>, field: &str, value: &str) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+        Ok(_) => problems.push(format!(
+            "{} must be an absolute http(s) URL, got `{}`.",
+            field, value
+        )),
+        Err(e) => problems.push(format!(
+            "{} is not a valid URL ({}): `{}`.",
+            field, e, value
+        )),
+    }
+}
+
+fn check_range(problems: &mut Vec<String>, field: &str, value: u64, range: RangeInclusive<u64>) {
+    if !range.contains(&value) {
+        problems.push(format!(
+            "{} must be between {} and {}, got {}.",
+            field,
+            range.start(),
+            range.end(),
+            value
+        ));
+    }
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -28,6 +273,28 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Serve `/metrics` on this port instead of the public one, so it can be
+    /// kept off the internet.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub metrics_port: Option<u16>,
+    /// How long in-flight requests and buffered tracking events get to
+    /// complete once shutdown starts, in total.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+    /// Apply pending migrations before serving. Instances starting together
+    /// take turns, see `migrations::run_migrations`.
+    #[serde(default)]
+    pub run_migrations_on_startup: bool,
+    /// Reverse proxies in front of the application. Only their
+    /// `Forwarded` and `X-Forwarded-For` headers are believed.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 impl DatabaseSettings {
@@ -60,6 +327,12 @@ pub struct EmailClientSettings {
     pub sender: String,
     pub auth_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub max_concurrent_requests: usize,
+    pub max_retries: u32,
+    pub batch_size: usize,
+    /// Emails, not requests: a batch counts once per recipient.
+    pub rate_limit_per_second: Option<u32>,
+    pub rate_limit_per_hour: Option<u32>,
 }
 
 impl EmailClientSettings {
@@ -70,9 +343,113 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        let limits = [
+            (
+                self.rate_limit_per_second,
+                std::time::Duration::from_secs(1),
+            ),
+            (
+                self.rate_limit_per_hour,
+                std::time::Duration::from_secs(60 * 60),
+            ),
+        ];
+        RateLimiter::new(
+            limits
+                .into_iter()
+                .filter_map(|(limit, period)| limit.map(|limit| (limit, period))),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct WebhookSettings {
+    /// Token the email provider must send as `Authorization: Bearer <token>`.
+    pub shared_secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct AdminSettings {
+    /// Token the protected admin endpoints require as
+    /// `Authorization: Bearer <token>`, until admins log in as users.
+    pub api_token: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TrackingSettings {
+    /// Master switch: issues only embed a tracking pixel when this is set
+    /// and the issue itself opts in.
+    pub open_tracking_enabled: bool,
+    /// Same as `open_tracking_enabled`, for link rewriting.
+    pub click_tracking_enabled: bool,
+    /// Key used to sign the tokens embedded in tracking URLs.
+    pub signing_key: Secret<String>,
+    pub flush_interval_milliseconds: u64,
+    /// Open events held in memory before new ones are dropped.
+    pub buffer_capacity: usize,
+}
+
+impl TrackingSettings {
+    pub fn flush_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.flush_interval_milliseconds)
+    }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct HealthSettings {
+    /// How long each readiness check may take before it counts as failed.
+    pub check_timeout_milliseconds: u64,
+    pub email_provider_check: EmailProviderCheck,
+}
+
+impl HealthSettings {
+    pub fn check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.check_timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TelemetrySettings {
+    /// Reported to the tracing backend as the `service.name` resource.
+    pub service_name: String,
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    /// Spans are not exported when unset.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// Share of new traces to sample, between 0 and 1. Traces started
+    /// upstream follow the caller's sampling decision instead.
+    pub sampling_ratio: f64,
+}
+
+/// Whether `/health/ready` probes the email provider, and whether the
+/// instance is reported as not ready when the provider is unreachable.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProviderCheck {
+    Disabled,
+    Optional,
+    Required,
+}
+
+/// Loads and validates the configuration. See `get_configuration_with`.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    get_configuration_with(&[])
+}
+
+/// Loads and validates the configuration from these layers, each
+/// overriding the ones before it:
+///
+/// 1. `configuration/base.yaml`;
+/// 2. `configuration/{APP_ENVIRONMENT}.yaml`, `local` by default;
+/// 3. `configuration/local.override.yaml`, if present, for uncommitted
+///    tweaks to a local setup;
+/// 4. each of `overlays`, e.g. given with `--config <path>`;
+/// 5. `APP__`-prefixed environment variables, such as
+///    `APP__APPLICATION__PORT=5001` for `application.port`.
+///
+/// References to secrets are then resolved, see `resolve_references`.
+pub fn get_configuration_with(overlays: &[PathBuf]) -> Result<Settings, ConfigurationError> {
     let mut settings = config::Config::default();
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
     let configuration_directory = base_path.join("configuration");
@@ -82,41 +459,391 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
+        .map_err(ConfigurationError::EnvironmentError)?;
 
     settings.merge(
         config::File::from(configuration_directory.join(environment.as_str())).required(true),
     )?;
+    settings.merge(
+        config::File::from(configuration_directory.join("local.override")).required(false),
+    )?;
+    for overlay in overlays {
+        settings.merge(config::File::from(overlay.as_path()).required(true))?;
+    }
 
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
-    settings.try_into()
+    resolve_references(&mut settings)?;
+    let settings: Settings = settings.try_into()?;
+    settings.validate(&environment)?;
+    Ok(settings)
 }
 
-pub enum Environment {
-    Local,
-    Production,
+/// Keeps secrets out of configuration files:
+///
+/// - `${NAME}` in a string value is replaced with the environment variable
+///   `NAME`, e.g. `auth_token: "${BREVO_API_KEY}"`;
+/// - a `<key>_file` setting is replaced with a `<key>` read from that file,
+///   e.g. `auth_token_file: /run/secrets/brevo_api_key` for a Docker or
+///   Kubernetes secret mount. It takes precedence over `<key>` itself.
+///
+/// Every variable or file that cannot be read is reported at once.
+fn resolve_references(settings: &mut config::Config) -> Result<(), ConfigurationError> {
+    let table = settings.clone().try_into()?;
+    let (mut values, mut files, mut problems) = (Vec::new(), Vec::new(), Vec::new());
+    collect_references("", table, &mut values, &mut files, &mut problems);
+
+    for (key, path) in files {
+        match std::fs::read_to_string(&path) {
+            // Secret files usually end with a newline, which is not part
+            // of the secret.
+            Ok(secret) => values.push((key, secret.trim_end_matches(['\r', '\n']).to_owned())),
+            Err(e) => problems.push(format!("{}_file: failed to read `{}`: {}", key, path, e)),
+        }
+    }
+    if !problems.is_empty() {
+        problems.sort();
+        return Err(ConfigurationError::ReferenceError(problems));
+    }
+    for (key, value) in values {
+        settings.set(&key, value)?;
+    }
+    Ok(())
 }
 
-impl Environment {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
+/// Walks `table`, interpolating string values into `values` and
+/// collecting `<key>_file` paths into `files`, both keyed by dotted path.
+fn collect_references(
+    prefix: &str,
+    table: HashMap<String, config::Value>,
+    values: &mut Vec<(String, String)>,
+    files: &mut Vec<(String, String)>,
+    problems: &mut Vec<String>,
+) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        if let Ok(table) = value.clone().into_table() {
+            collect_references(&path, table, values, files, problems);
+            continue;
+        }
+        let value = match value.into_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        let interpolated = match interpolate(&value) {
+            Ok(interpolated) => interpolated,
+            Err(e) => {
+                problems.push(format!("{}: {}", path, e));
+                continue;
+            }
+        };
+        if let Some(key) = path.strip_suffix("_file") {
+            files.push((key.to_owned(), interpolated));
+        } else if interpolated != value {
+            values.push((path, interpolated));
         }
     }
 }
 
+/// Replaces each `${NAME}` in `value` with the environment variable `NAME`.
+fn interpolate(value: &str) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or("`${` is not closed by `}`.")?;
+        let name = &rest[start + 2..end];
+        let variable = std::env::var(name)
+            .map_err(|_| format!("the environment variable `{}` is not set.", name))?;
+        interpolated.push_str(&rest[..start]);
+        interpolated.push_str(&variable);
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// The name of a deployment environment, such as `local`, `staging` or
+/// `production`. Each has its settings in `configuration/{name}.yaml`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Environment(String);
+
+impl Environment {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.0 == "production"
+    }
+}
+
 impl TryFrom<String> for Environment {
     type Error = String;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            other => Err(format!(
-                "{} is not a supported environment. Use either `local` or `production`.",
-                other
-            )),
+        let name = s.to_lowercase();
+        // The name becomes a file name, so keep it to a safe alphabet.
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+        if is_valid {
+            Ok(Self(name))
+        } else {
+            Err(format!(
+                "`{}` is not a valid environment name. Use letters, digits, `-` and `_`, \
+                matching a file in `configuration/`.",
+                s
+            ))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{get_configuration_with, interpolate, ConfigurationError, Environment, Settings};
+    use claim::assert_ok;
+    use secrecy::ExposeSecret;
+    use secrecy::Secret;
+
+    fn settings(environment: &str) -> Settings {
+        let mut settings = config::Config::default();
+        for yaml in [
+            include_str!("../configuration/base.yaml"),
+            match environment {
+                "production" => include_str!("../configuration/production.yaml"),
+                _ => include_str!("../configuration/local.yaml"),
+            },
+        ] {
+            settings
+                .merge(config::File::from_str(yaml, config::FileFormat::Yaml))
+                .unwrap();
+        }
+        settings.try_into().unwrap()
+    }
+
+    /// `settings`, with the secrets production must provide.
+    fn with_production_secrets(mut settings: Settings) -> Settings {
+        settings.email_client.auth_token = Secret::new("email-auth-token".into());
+        settings.webhooks.shared_secret = Secret::new("webhook-secret".into());
+        settings.admin.api_token = Secret::new("admin-api-token".into());
+        settings.tracking.signing_key = Secret::new("tracking-signing-key".into());
+        settings
+    }
+
+    fn environment(name: &str) -> Environment {
+        name.to_string().try_into().unwrap()
+    }
+
+    fn problems(result: Result<(), ConfigurationError>) -> Vec<String> {
+        match result {
+            Err(ConfigurationError::ValidationError(problems)) => problems,
+            other => panic!("Expected validation problems, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_shipped_configuration_is_valid() {
+        assert_ok!(settings("local").validate(&environment("local")));
+        assert_ok!(
+            with_production_secrets(settings("production")).validate(&environment("production"))
+        );
+    }
+
+    #[test]
+    fn placeholder_secrets_are_rejected_in_production() {
+        let problems = problems(settings("production").validate(&environment("production")));
+
+        assert_eq!(problems.len(), 4, "{:#?}", problems);
+        for field in [
+            "email_client.auth_token",
+            "webhooks.shared_secret",
+            "admin.api_token",
+            "tracking.signing_key",
+        ] {
+            assert!(
+                problems.iter().any(|p| p.starts_with(field)),
+                "{} was not reported",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut settings = settings("local");
+        settings.email_client.sender = "not an email".into();
+        settings.email_client.base_url = "/v3/smtp".into();
+        settings.email_client.auth_token = Secret::new("".into());
+        settings.telemetry.sampling_ratio = 2.0;
+
+        let problems = problems(settings.validate(&environment("local")));
+
+        assert_eq!(problems.len(), 4, "{:#?}", problems);
+        for field in [
+            "email_client.sender",
+            "email_client.base_url",
+            "email_client.auth_token",
+            "telemetry.sampling_ratio",
+        ] {
+            assert!(
+                problems.iter().any(|p| p.starts_with(field)),
+                "{} was not reported",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn ssl_is_required_in_production() {
+        let mut settings = with_production_secrets(settings("local"));
+        settings.application.metrics_port = Some(9000);
+
+        let problems = problems(settings.validate(&environment("production")));
+
+        assert_eq!(
+            problems,
+            ["database.required_ssl must be true in production."]
+        );
+    }
+
+    #[test]
+    fn metrics_need_their_own_port_in_production() {
+        let mut settings = with_production_secrets(settings("production"));
+        settings.application.metrics_port = None;
+
+        let problems = problems(settings.validate(&environment("production")));
+
+        assert_eq!(problems.len(), 1, "{:#?}", problems);
+        assert!(problems[0].starts_with("application.metrics_port"));
+    }
+
+    #[test]
+    fn timeouts_out_of_range_are_rejected() {
+        let mut settings = settings("local");
+        settings.email_client.timeout_milliseconds = 0;
+        settings.application.shutdown_timeout_seconds = 3_600;
+
+        let problems = problems(settings.validate(&environment("local")));
+
+        assert_eq!(
+            problems,
+            [
+                "application.shutdown_timeout_seconds must be between 1 and 300, got 3600.",
+                "email_client.timeout_milliseconds must be between 100 and 120000, got 0.",
+            ]
+        );
+    }
+
+    #[test]
+    fn any_well_formed_environment_name_is_accepted() {
+        for name in ["local", "staging", "ci", "Test", "eu-west_1"] {
+            let environment = Environment::try_from(name.to_string()).unwrap();
+            assert_eq!(environment.as_str(), name.to_lowercase());
+        }
+        assert!(environment("production").is_production());
+        assert!(!environment("staging").is_production());
+    }
+
+    #[test]
+    fn environment_names_that_are_not_safe_file_names_are_rejected() {
+        for name in ["", "../secrets", "prod/eu", "staging.yaml"] {
+            assert!(
+                Environment::try_from(name.to_string()).is_err(),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn overlays_apply_in_order_over_the_environment_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        let first = directory.join("first.yaml");
+        let second = directory.join("second.yaml");
+        std::fs::write(&first, "application:\n  port: 1111\n  host: 10.0.0.1\n").unwrap();
+        std::fs::write(&second, "application:\n  port: 2222\n").unwrap();
+
+        let settings = get_configuration_with(&[first, second]).unwrap();
+
+        assert_eq!(settings.application.port, 2222);
+        assert_eq!(settings.application.host, "10.0.0.1");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn environment_variables_are_interpolated() {
+        std::env::set_var("CONFIGURATION_TEST_HOST", "db.internal");
+
+        let interpolated = interpolate("postgres://${CONFIGURATION_TEST_HOST}:5432").unwrap();
+
+        assert_eq!(interpolated, "postgres://db.internal:5432");
+        assert_eq!(interpolate("no references").unwrap(), "no references");
+    }
+
+    #[test]
+    fn missing_or_unclosed_references_are_rejected() {
+        assert!(interpolate("${CONFIGURATION_TEST_UNSET}").is_err());
+        assert!(interpolate("${CONFIGURATION_TEST_HOST").is_err());
+    }
+
+    #[test]
+    fn secrets_are_read_from_files_and_environment_variables() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        let secret = directory.join("auth_token");
+        std::fs::write(&secret, "token-from-file\n").unwrap();
+        let overlay = directory.join("secrets.yaml");
+        std::env::set_var("CONFIGURATION_TEST_WEBHOOK_SECRET", "secret-from-env");
+        std::fs::write(
+            &overlay,
+            format!(
+                "email_client:\n  auth_token_file: {}\nwebhooks:\n  shared_secret: \"${{CONFIGURATION_TEST_WEBHOOK_SECRET}}\"\n",
+                secret.display()
+            ),
+        )
+        .unwrap();
+
+        let settings = get_configuration_with(&[overlay]).unwrap();
+
+        assert_eq!(
+            settings.email_client.auth_token.expose_secret(),
+            "token-from-file"
+        );
+        assert_eq!(
+            settings.webhooks.shared_secret.expose_secret(),
+            "secret-from-env"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn every_unresolved_reference_is_reported_at_once() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        let overlay = directory.join("secrets.yaml");
+        std::fs::write(
+            &overlay,
+            "email_client:\n  auth_token_file: /nonexistent/auth_token\n\
+            webhooks:\n  shared_secret: \"${CONFIGURATION_TEST_UNSET}\"\n",
+        )
+        .unwrap();
+
+        let problems = match get_configuration_with(&[overlay]) {
+            Err(ConfigurationError::ReferenceError(problems)) => problems,
+            other => panic!("Expected unresolved references, got {:?}", other),
+        };
+
+        assert_eq!(problems.len(), 2, "{:#?}", problems);
+        assert!(problems[0].starts_with("email_client.auth_token_file"));
+        assert!(problems[1].starts_with("webhooks.shared_secret"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use crate::suppression::email_hash;
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// How long an emailed download link stays valid.
+pub const EXPORT_LINK_VALIDITY_HOURS: i64 = 24;
+
+/// Everything held about a subscriber, as handed over on request.
+#[derive(serde::Serialize)]
+pub struct SubscriberExport {
+    pub exported_at: DateTime<Utc>,
+    pub subscriber: ExportedSubscriber,
+    /// Tokens are credentials: only their SHA-256 is exported.
+    pub subscription_tokens: Vec<ExportedToken>,
+    pub status_changes: Vec<ExportedStatusChange>,
+    pub suppression: Option<ExportedSuppression>,
+    pub deliveries: Vec<ExportedDelivery>,
+    pub skipped_confirmation_emails: Vec<ExportedSkippedConfirmationEmail>,
+    /// Delivery, bounce, complaint, open and click reports from the email
+    /// provider.
+    pub email_events: Vec<ExportedEmailEvent>,
+    pub opens: Vec<ExportedOpen>,
+    pub clicks: Vec<ExportedClick>,
+    /// Audited actions taken on the subscriber, without who took them.
+    pub audit_log: Vec<ExportedAuditEntry>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedToken {
+    pub token_sha256: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedStatusChange {
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSuppression {
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSkippedConfirmationEmail {
+    pub status: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedEmailEvent {
+    pub event: String,
+    pub newsletter_issue_id: Option<Uuid>,
+    pub reason: Option<String>,
+    pub link: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedOpen {
+    pub newsletter_issue_id: Uuid,
+    pub opened_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedClick {
+    pub newsletter_issue_id: Uuid,
+    pub url: String,
+    pub clicked_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedAuditEntry {
+    pub occurred_at: DateTime<Utc>,
+    pub action: String,
+    pub details: Value,
+}
+
+/// Collects everything held about a subscriber, or `None` if there is no
+/// such subscriber.
+#[tracing::instrument(name = "Export a subscriber's data", skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberExport>, sqlx::Error> {
+    // One snapshot for every table.
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut transaction)
+        .await?;
+
+    let subscriber = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+    let hash = email_hash(&subscriber.email);
+
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|r| ExportedToken {
+        token_sha256: sha256_hex(&r.subscription_token),
+    })
+    .collect();
+    let status_changes = sqlx::query_as!(
+        ExportedStatusChange,
+        r#"
+        SELECT from_status, to_status, changed_at FROM subscription_status_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let suppression = sqlx::query_as!(
+        ExportedSuppression,
+        r#"SELECT reason, source, created_at FROM suppressions WHERE email_hash = $1"#,
+        hash
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        ExportedDelivery,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.status, d.failure_reason, d.created_at,
+            d.updated_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE d.subscriber_id = $1
+        ORDER BY d.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let skipped_confirmation_emails = sqlx::query_as!(
+        ExportedSkippedConfirmationEmail,
+        r#"
+        SELECT status, reason, created_at FROM skipped_confirmation_emails
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let email_events = sqlx::query_as!(
+        ExportedEmailEvent,
+        r#"
+        SELECT event, newsletter_issue_id, reason, link, occurred_at FROM email_events
+        WHERE email_hash = $1
+        ORDER BY occurred_at
+        "#,
+        hash
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let opens = sqlx::query_as!(
+        ExportedOpen,
+        r#"
+        SELECT newsletter_issue_id, opened_at, user_agent FROM open_events
+        WHERE subscriber_id = $1
+        ORDER BY opened_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    let clicks = sqlx::query_as!(
+        ExportedClick,
+        r#"
+        SELECT newsletter_issue_id, url, clicked_at, user_agent FROM click_events
+        WHERE subscriber_id = $1
+        ORDER BY clicked_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    // Entries target subscribers by id, or by email hash where the address
+    // is all there is, e.g. for suppressions.
+    let audit_log = sqlx::query_as!(
+        ExportedAuditEntry,
+        r#"
+        SELECT occurred_at, action, details FROM audit_log
+        WHERE target = $1 OR target = $2
+        ORDER BY id
+        "#,
+        subscriber_id.to_string(),
+        hash
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Some(SubscriberExport {
+        exported_at: Utc::now(),
+        subscriber,
+        subscription_tokens,
+        status_changes,
+        suppression,
+        deliveries,
+        skipped_confirmation_emails,
+        email_events,
+        opens,
+        clicks,
+        audit_log,
+    }))
+}
+
+/// Creates a download link token for the subscriber's data, valid for
+/// `EXPORT_LINK_VALIDITY_HOURS`. Only its hash is stored.
+#[tracing::instrument(name = "Create a data export link", skip(executor))]
+pub async fn create_export_token(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let mut rng = thread_rng();
+    let export_token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO data_exports (token_hash, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        sha256_hex(&export_token),
+        subscriber_id,
+        now,
+        now + Duration::hours(EXPORT_LINK_VALIDITY_HOURS)
+    )
+    .execute(executor)
+    .await?;
+    Ok(export_token)
+}
+
+/// Whether the subscriber was sent a download link that has not expired yet.
+#[tracing::instrument(name = "Check for a pending data export link", skip(executor))]
+pub async fn has_pending_export_link(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM data_exports WHERE subscriber_id = $1 AND expires_at > now()
+        ) AS "pending!"
+        "#,
+        subscriber_id
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.pending)
+}
+
+/// Who a download link was issued for, and until when it is valid.
+pub struct ExportLink {
+    pub subscriber_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Look up a data export link", skip(executor, export_token))]
+pub async fn find_export_link(
+    executor: impl PgExecutor<'_>,
+    export_token: &str,
+) -> Result<Option<ExportLink>, sqlx::Error> {
+    sqlx::query_as!(
+        ExportLink,
+        r#"SELECT subscriber_id, expires_at FROM data_exports WHERE token_hash = $1"#,
+        sha256_hex(export_token)
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+fn sha256_hex(value: &str) -> String {
+    format!("{:x}", Sha256::digest(value.as_bytes()))
+}
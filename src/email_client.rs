@@ -1,6 +1,10 @@
 use crate::domain::SubscriberEmail;
+use crate::monitoring::record_email_client_request;
+use crate::rate_limiter::RateLimiter;
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use crate::telemetry::trace_context_headers;
 use reqwest::header;
-use reqwest::Client;
+use reqwest::{Client, Response, StatusCode};
 use secrecy::ExposeSecret;
 use secrecy::Secret;
 
@@ -35,14 +39,81 @@ struct SendEmailRequest<'a> {
     to: Vec<RecipientsEmail<'a>>,
     subject: &'a str,
     html_content: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<EmailHeaders>,
 }
 
+/// Custom headers Brevo adds to the emails it sends, so a delivered email
+/// can be traced back to the request that triggered it.
+#[derive(serde::Serialize, Debug)]
+struct EmailHeaders {
+    #[serde(rename = "X-Request-Id")]
+    request_id: String,
+}
+
+impl EmailHeaders {
+    fn current() -> Option<Self> {
+        RequestId::current().map(|request_id| Self {
+            request_id: request_id.to_string(),
+        })
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MessageVersion<'a> {
+    to: Vec<RecipientsEmail<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html_content: Option<&'a str>,
+}
+
+/// One call to Brevo's transactional endpoint carrying a personalised
+/// `messageVersions` entry per recipient.
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SendBatchEmailRequest<'a> {
+    sender: SenderEmail<'a>,
+    subject: &'a str,
+    html_content: &'a str,
+    message_versions: Vec<MessageVersion<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<EmailHeaders>,
+}
+
+/// Brevo answers a single send with `messageId` and a batch with one
+/// `messageIds` entry per message version, in order.
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct SendEmailResponse {
+    message_id: Option<String>,
+    #[serde(default)]
+    message_ids: Vec<String>,
+}
+
+/// A recipient of `EmailClient::send_email_batch`.
+#[derive(Debug)]
+pub struct BatchRecipient<'a> {
+    pub email: &'a SubscriberEmail,
+    /// Overrides the batch HTML for this recipient only.
+    pub html: Option<&'a str>,
+}
+
+/// Brevo rejects requests carrying more message versions than this.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
 #[derive(Clone, Debug)]
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     auth_token: Secret<String>,
+    rate_limiter: RateLimiter,
+    max_concurrent_requests: usize,
+    max_retries: u32,
+    /// Longest a `429` may ask us to wait before retrying. Longer waits fail
+    /// the request instead of holding up the send.
+    max_retry_delay: std::time::Duration,
+    batch_size: usize,
 }
 
 impl EmailClient {
@@ -57,43 +128,223 @@ impl EmailClient {
             base_url,
             sender,
             auth_token,
+            rate_limiter: RateLimiter::unlimited(),
+            max_concurrent_requests: 1,
+            max_retries: 0,
+            max_retry_delay: timeout,
+            batch_size: 1,
         }
     }
 
+    /// Throttles every request to the provider through `rate_limiter` and
+    /// retries up to `max_retries` times when the provider answers with
+    /// `429 Too Many Requests`, unless it asks for a longer wait than the
+    /// request timeout.
+    pub fn with_delivery_limits(
+        mut self,
+        rate_limiter: RateLimiter,
+        max_concurrent_requests: usize,
+        max_retries: u32,
+    ) -> Self {
+        self.rate_limiter = rate_limiter;
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How many requests callers fanning out over many recipients should
+    /// keep in flight at once.
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests
+    }
+
+    /// Groups up to `batch_size` recipients into a single provider call,
+    /// capped at `MAX_BATCH_SIZE`.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// How many recipients callers should hand to `send_email_batch` at once.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
     #[tracing::instrument(name = "Sending a confirmation email")]
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<Option<String>, reqwest::Error> {
         let request_body = SendEmailRequest {
             sender: SenderEmail::new(self.sender.as_ref(), "Xplorare"),
             to: vec![RecipientsEmail::new(recipient.as_ref(), "User")],
             subject,
             html_content: html,
+            headers: EmailHeaders::current(),
         };
 
         tracing::info!("request body {:?}", request_body);
-        let url = format!("{}/email", self.base_url);
-        let _builder = self
-            .http_client
-            .post(&url)
-            .header("api-key", self.auth_token.expose_secret())
-            .header(header::CONTENT_TYPE, "application/json")
-            .json(&request_body)
-            .send()
+        let response = self.post_email(&request_body, 1).await?;
+        Ok(response.message_id)
+    }
+
+    /// Sends the same email to every recipient in a single provider call,
+    /// one message version each.
+    ///
+    /// Either the whole batch is accepted or none of it is: callers should
+    /// treat an error as a failure for every recipient in `recipients`.
+    /// On success, returns the provider message id of each recipient, in
+    /// order, when the provider reported one.
+    #[tracing::instrument(
+        name = "Sending a batch of emails",
+        skip(self, recipients, html),
+        fields(batch_size = recipients.len())
+    )]
+    pub async fn send_email_batch(
+        &self,
+        recipients: &[BatchRecipient<'_>],
+        subject: &str,
+        html: &str,
+    ) -> Result<Vec<Option<String>>, reqwest::Error> {
+        let request_body = SendBatchEmailRequest {
+            sender: SenderEmail::new(self.sender.as_ref(), "Xplorare"),
+            subject,
+            html_content: html,
+            message_versions: recipients
+                .iter()
+                .map(|recipient| MessageVersion {
+                    to: vec![RecipientsEmail::new(recipient.email.as_ref(), "User")],
+                    html_content: recipient.html,
+                })
+                .collect(),
+            headers: EmailHeaders::current(),
+        };
+
+        let mut message_ids = self
+            .post_email(&request_body, recipients.len() as u32)
             .await?
-            .error_for_status()?;
-        Ok(())
+            .message_ids
+            .into_iter();
+        Ok(recipients.iter().map(|_| message_ids.next()).collect())
+    }
+
+    /// Checks the provider can be reached. Any HTTP response will do: the
+    /// base URL is not an endpoint we expect to succeed.
+    #[tracing::instrument(name = "Check the email provider is reachable", skip(self))]
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        self.http_client
+            .get(&self.base_url)
+            .headers(trace_context_headers())
+            .send()
+            .await
+            .map(|_| ())
+    }
+
+    /// Posts `request_body`, carrying `emails` emails, to the provider. Each
+    /// attempt first waits on the rate limiter for all of them, and
+    /// `429 Too Many Requests` is backed off.
+    async fn post_email(
+        &self,
+        request_body: &impl serde::Serialize,
+        emails: u32,
+    ) -> Result<SendEmailResponse, reqwest::Error> {
+        let url = format!("{}/email", self.base_url);
+        let request_id = RequestId::current();
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.acquire(emails).await;
+            let start = std::time::Instant::now();
+            let mut request = self
+                .http_client
+                .post(&url)
+                .header("api-key", self.auth_token.expose_secret())
+                .header(header::CONTENT_TYPE, "application/json")
+                .headers(trace_context_headers());
+            if let Some(request_id) = &request_id {
+                request = request.header(REQUEST_ID_HEADER, request_id.as_ref());
+            }
+            let response = match request.json(request_body).send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    let outcome = if e.is_timeout() { "timeout" } else { "error" };
+                    record_email_client_request(outcome, start);
+                    return Err(e);
+                }
+            };
+            record_email_client_request(request_outcome(response.status()), start);
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS && attempt < self.max_retries {
+                let delay = retry_after(&response).unwrap_or_else(|| backoff(attempt));
+                if delay <= self.max_retry_delay {
+                    tracing::warn!(
+                        attempt,
+                        retry_after_ms = delay.as_millis() as u64,
+                        "The email provider is rate limiting us, backing off"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+                tracing::warn!(
+                    attempt,
+                    retry_after_ms = delay.as_millis() as u64,
+                    "The email provider asked us to wait longer than the request timeout, giving up"
+                );
+            }
+
+            let body = response.error_for_status()?.bytes().await?;
+            // A 2xx means the email was accepted even if we cannot make
+            // sense of the body, so a missing message id is not an error.
+            return Ok(serde_json::from_slice(&body).unwrap_or_default());
+        }
+    }
+}
+
+/// Label for a provider response in the `email_client_*` metrics.
+fn request_outcome(status: StatusCode) -> &'static str {
+    if status.is_success() {
+        "success"
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        "rate_limited"
+    } else if status.is_client_error() {
+        "client_error"
+    } else {
+        "server_error"
+    }
+}
+
+fn backoff(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_secs(1 << attempt.min(6))
+}
+
+/// Reads the `Retry-After` header, either as a number of seconds or as an
+/// HTTP date.
+fn retry_after(response: &Response) -> Option<std::time::Duration> {
+    let value = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
     }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
 }
 
 #[cfg(test)]
 mod tests {
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{BatchRecipient, EmailClient};
+    use crate::rate_limiter::RateLimiter;
     use claim::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -118,6 +369,31 @@ mod tests {
         }
     }
 
+    struct SendBatchEmailBodyMatcher {
+        versions: usize,
+    }
+
+    impl wiremock::Match for SendBatchEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                let versions = body["messageVersions"].as_array();
+                body.get("sender").is_some()
+                    && body.get("subject").is_some()
+                    && body.get("htmlContent").is_some()
+                    && body.get("to").is_none()
+                    && versions.is_some_and(|versions| {
+                        versions.len() == self.versions
+                            && versions
+                                .iter()
+                                .all(|v| v["to"].as_array().is_some_and(|to| to.len() == 1))
+                    })
+            } else {
+                false
+            }
+        }
+    }
+
     fn subject() -> String {
         Sentence(1..2).fake()
     }
@@ -130,6 +406,13 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 
+    fn batch_recipients(emails: &[SubscriberEmail]) -> Vec<BatchRecipient<'_>> {
+        emails
+            .iter()
+            .map(|email| BatchRecipient { email, html: None })
+            .collect()
+    }
+
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
             base_url,
@@ -146,6 +429,8 @@ mod tests {
 
         Mock::given(path("/email"))
             .and(method("POST"))
+            .and(header_exists("api-key"))
+            .and(SendEmailBodyMatcher)
             .respond_with(ResponseTemplate::new(200))
             .expect(1)
             .mount(&mock_server)
@@ -197,4 +482,225 @@ mod tests {
 
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn send_email_retries_after_a_429_honouring_retry_after() {
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_delivery_limits(RateLimiter::unlimited(), 1, 3);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_without_waiting_when_retry_after_exceeds_the_timeout() {
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_delivery_limits(RateLimiter::unlimited(), 1, 3);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "86400"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            email_client.send_email(&email(), &subject(), &content()),
+        )
+        .await
+        .expect("send_email waited for Retry-After");
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_once_429_retries_are_exhausted() {
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_delivery_limits(RateLimiter::unlimited(), 1, 2);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_sends_one_message_version_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_batch_size(10);
+        let emails: Vec<_> = (0..3).map(|_| email()).collect();
+        let recipients = batch_recipients(&emails);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(header_exists("api-key"))
+            .and(SendBatchEmailBodyMatcher { versions: 3 })
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_batch(&recipients, &subject(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_if_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_batch_size(10);
+        let emails: Vec<_> = (0..3).map(|_| email()).collect();
+        let recipients = batch_recipients(&emails);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_batch(&recipients, &subject(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_takes_a_rate_limit_token_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri())
+            .with_batch_size(10)
+            .with_delivery_limits(
+                RateLimiter::new([(3, std::time::Duration::from_secs(60))]),
+                1,
+                0,
+            );
+        let emails: Vec<_> = (0..3).map(|_| email()).collect();
+        let recipients = batch_recipients(&emails);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(
+            email_client
+                .send_email_batch(&recipients, &subject(), &content())
+                .await
+        );
+        // The batch used up the 3 emails a minute allows.
+        let next = tokio::time::timeout(
+            std::time::Duration::from_millis(500),
+            email_client.send_email(&email(), &subject(), &content()),
+        )
+        .await;
+
+        assert_err!(next);
+    }
+
+    #[test]
+    fn batch_size_is_capped_at_the_provider_limit() {
+        let email_client = email_client("http://localhost".into()).with_batch_size(5000);
+        assert_eq!(email_client.batch_size(), super::MAX_BATCH_SIZE);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_returns_the_provider_message_ids_in_order() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_batch_size(10);
+        let emails: Vec<_> = (0..2).map(|_| email()).collect();
+        let recipients = batch_recipients(&emails);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(serde_json::json!({ "messageIds": ["<first>", "<second>"] })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let message_ids = email_client
+            .send_email_batch(&recipients, &subject(), &content())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            message_ids,
+            [Some("<first>".to_string()), Some("<second>".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_sends_personalised_html_per_message_version() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).with_batch_size(10);
+        let emails: Vec<_> = (0..2).map(|_| email()).collect();
+        let recipients = [
+            BatchRecipient {
+                email: &emails[0],
+                html: Some("<p>Personalised</p>"),
+            },
+            BatchRecipient {
+                email: &emails[1],
+                html: None,
+            },
+        ];
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(SendBatchEmailBodyMatcher { versions: 2 })
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client
+            .send_email_batch(&recipients, &subject(), &content())
+            .await
+            .unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body["messageVersions"][0]["htmlContent"],
+            "<p>Personalised</p>"
+        );
+        assert!(body["messageVersions"][1].get("htmlContent").is_none());
+    }
 }
@@ -0,0 +1,165 @@
+use crate::audit::{record_audit_event, AuditActor};
+use crate::suppression::{add_suppression, email_hash, SuppressionReason};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+/// What erasing a subscriber touched.
+#[derive(serde::Serialize, Debug)]
+pub struct Erasure {
+    pub id: Uuid,
+    /// Rows kept for issue stats, detached from the subscriber.
+    pub anonymised_deliveries: u64,
+    pub anonymised_opens: u64,
+    pub anonymised_clicks: u64,
+    pub anonymised_email_events: u64,
+    /// `false` if the address was already suppressed.
+    pub suppressed: bool,
+}
+
+/// Rows of a subscriber kept for issue stats, detached from them.
+#[derive(Debug)]
+pub struct Anonymisation {
+    pub deliveries: u64,
+    pub opens: u64,
+    pub clicks: u64,
+    pub email_events: u64,
+}
+
+/// Erases a subscriber, or returns `None` if there is no such subscriber.
+///
+/// The subscription goes, along with its tokens, status history and export
+/// links. Their other rows are anonymised, see `anonymise_subscriber`. The
+/// address is suppressed by its hash so it cannot be subscribed or imported
+/// again.
+///
+/// The erasure is audited as `subscriber.erased`; commit `transaction` to
+/// make it final.
+#[tracing::instrument(name = "Erase a subscriber", skip(transaction, actor))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    actor: &AuditActor,
+    subscriber_id: Uuid,
+) -> Result<Option<Erasure>, sqlx::Error> {
+    let email = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let email = match email {
+        Some(row) => row.email,
+        None => return Ok(None),
+    };
+    let anonymised = anonymise_subscriber(transaction, subscriber_id, &email).await?;
+
+    // Tokens, status history and export links go with it, by cascade.
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    let suppressed = add_suppression(
+        &mut *transaction,
+        &email,
+        SuppressionReason::Legal,
+        "erasure",
+    )
+    .await?;
+
+    let erasure = Erasure {
+        id: subscriber_id,
+        anonymised_deliveries: anonymised.deliveries,
+        anonymised_opens: anonymised.opens,
+        anonymised_clicks: anonymised.clicks,
+        anonymised_email_events: anonymised.email_events,
+        suppressed,
+    };
+    record_audit_event(
+        &mut *transaction,
+        actor,
+        "subscriber.erased",
+        Some(&subscriber_id.to_string()),
+        serde_json::json!({
+            "anonymised_deliveries": erasure.anonymised_deliveries,
+            "anonymised_opens": erasure.anonymised_opens,
+            "anonymised_clicks": erasure.anonymised_clicks,
+            "anonymised_email_events": erasure.anonymised_email_events,
+            "suppressed": erasure.suppressed,
+        }),
+    )
+    .await?;
+    Ok(Some(erasure))
+}
+
+/// Detaches the deliveries, opens, clicks and provider events of the
+/// subscriber with `email` from them, ahead of deleting the subscription.
+///
+/// The rows are kept so issue stats do not change, but move to a random id
+/// and a placeholder address shared by nothing else, and lose anything that
+/// could point back at the subscriber.
+#[tracing::instrument(name = "Anonymise a subscriber", skip(transaction, email))]
+pub async fn anonymise_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &str,
+) -> Result<Anonymisation, sqlx::Error> {
+    // Stats count distinct subscribers and addresses, so all of the
+    // subscriber's rows must share the same stand-ins.
+    let pseudonym = Uuid::new_v4();
+    let placeholder = format!("{}@erased.invalid", pseudonym.to_simple());
+
+    let deliveries = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET subscriber_id = $2, subscriber_email = $3, provider_message_id = NULL,
+            failure_reason = NULL
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        pseudonym,
+        placeholder
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    let opens = sqlx::query!(
+        r#"
+        UPDATE open_events SET subscriber_id = $2, user_agent = NULL
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        pseudonym
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    let clicks = sqlx::query!(
+        r#"
+        UPDATE click_events SET subscriber_id = $2, user_agent = NULL
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        pseudonym
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    // Bounce and complaint reasons often quote the address.
+    let email_events = sqlx::query!(
+        r#"
+        UPDATE email_events
+        SET email = $2, email_hash = $3, provider_message_id = NULL, reason = NULL
+        WHERE email_hash = $1
+        "#,
+        email_hash(email),
+        placeholder,
+        email_hash(&placeholder)
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    Ok(Anonymisation {
+        deliveries,
+        opens,
+        clicks,
+        email_events,
+    })
+}
@@ -1,7 +1,21 @@
+pub mod audit;
+pub mod authentication;
+pub mod cli;
 pub mod configuration;
+pub mod data_export;
 pub mod domain;
 pub mod email_client;
+pub mod erasure;
 pub mod helper;
+pub mod migrations;
+pub mod monitoring;
+pub mod problem;
+pub mod rate_limiter;
+pub mod request_id;
 pub mod routes;
 pub mod startup;
+pub mod subscription_status;
+pub mod suppression;
 pub mod telemetry;
+pub mod tracking;
+pub mod users;
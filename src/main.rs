@@ -1,14 +1,51 @@
-use zero2prod::configuration::get_configuration;
+use clap::Parser;
+use std::path::PathBuf;
+use zero2prod::configuration::get_configuration_with;
+use zero2prod::migrations::run_migrations;
 use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, init_subscriber, shutdown_tracer_provider};
+
+#[derive(clap::Parser, Debug)]
+#[clap(name = "zero2prod", version, about = "Serves the newsletter")]
+struct Cli {
+    /// Extra configuration file, layered over `configuration/`. Repeatable.
+    #[clap(long = "config", global = true, value_name = "PATH")]
+    config: Vec<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Serves the API until SIGTERM or Ctrl-C. The default.
+    Serve,
+    /// Applies pending database migrations, then exits.
+    Migrate,
+}
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let cli = Cli::parse();
+    let configuration = get_configuration_with(&cli.config).expect("Failed to read configuration");
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        &configuration.telemetry,
+    );
     init_subscriber(subscriber);
-    let configuration = get_configuration().expect("Failed to read configuration");
 
-    let application = Application::build(configuration.clone()).await?;
-    application.run().await?;
-    Ok(())
+    let outcome = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            let application = Application::build(configuration.clone()).await?;
+            application.run().await
+        }
+        Command::Migrate => run_migrations(&configuration.database)
+            .await
+            .map_err(std::io::Error::other),
+    };
+    tokio::task::spawn_blocking(shutdown_tracer_provider)
+        .await
+        .expect("Failed to flush pending spans");
+    outcome
 }
@@ -0,0 +1,60 @@
+use crate::configuration::DatabaseSettings;
+use crate::helper::error_chain_fmt;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{Connection, PgConnection};
+
+/// The migrations embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Key of the session-level advisory lock held while migrating, so that
+/// instances starting together apply the migrations one at a time.
+const MIGRATION_LOCK_KEY: i64 = 0x7a32_7072_6f64_6d67;
+
+#[derive(thiserror::Error)]
+pub enum MigrationError {
+    #[error("Failed to connect to the database")]
+    ConnectionError(#[source] sqlx::Error),
+    #[error("Failed to take the migration lock")]
+    LockError(#[source] sqlx::Error),
+    #[error("Failed to apply the migrations")]
+    MigrateError(#[from] MigrateError),
+}
+
+impl std::fmt::Debug for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Applies the embedded migrations that have not been applied yet.
+///
+/// Runs on a dedicated connection holding an advisory lock, waiting for
+/// any other instance that is migrating to finish first; by then there is
+/// usually nothing left to do. The lock goes with the connection, so it is
+/// released even if a migration fails.
+#[tracing::instrument(name = "Run database migrations", skip(configuration))]
+pub async fn run_migrations(configuration: &DatabaseSettings) -> Result<(), MigrationError> {
+    let mut connection = PgConnection::connect_with(&configuration.with_db())
+        .await
+        .map_err(MigrationError::ConnectionError)?;
+
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .fetch_one(&mut connection)
+        .await
+        .map_err(MigrationError::LockError)?;
+    if !locked {
+        tracing::info!("Waiting for another instance to finish migrating");
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut connection)
+            .await
+            .map_err(MigrationError::LockError)?;
+    }
+
+    MIGRATOR.run(&mut connection).await?;
+    tracing::info!("The database schema is up to date");
+    // Closing the session releases the lock.
+    let _ = connection.close().await;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use once_cell::sync::Lazy;
+use std::time::Instant;
+
+/// Histogram buckets, in seconds, shared by every `*_duration_seconds` metric.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The process-wide Prometheus recorder. `metrics` only accepts a single
+/// global recorder, so it is installed on first use and shared by every
+/// `Application` built in the process.
+static PROMETHEUS: Lazy<PrometheusHandle> = Lazy::new(|| {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_duration_seconds".into()), LATENCY_BUCKETS)
+        .expect("Invalid histogram buckets")
+        .install_recorder()
+        .expect("Failed to install the Prometheus recorder");
+    describe_metrics();
+    handle
+});
+
+/// Installs the recorder. Metrics recorded before this are dropped, so call
+/// it before serving any traffic.
+pub fn init_metrics() {
+    Lazy::force(&PROMETHEUS);
+}
+
+pub fn prometheus_handle() -> PrometheusHandle {
+    PROMETHEUS.clone()
+}
+
+fn describe_metrics() {
+    metrics::describe_counter!(
+        "http_requests_total",
+        "HTTP requests handled, by method, route and status."
+    );
+    metrics::describe_histogram!(
+        "http_request_duration_seconds",
+        metrics::Unit::Seconds,
+        "Time spent handling HTTP requests, by method, route and status."
+    );
+    metrics::describe_counter!(
+        "email_client_requests_total",
+        "Requests sent to the email provider, by outcome."
+    );
+    metrics::describe_histogram!(
+        "email_client_request_duration_seconds",
+        metrics::Unit::Seconds,
+        "Latency of requests sent to the email provider, by outcome."
+    );
+    metrics::describe_gauge!("db_pool_connections", "Open Postgres connections.");
+    metrics::describe_gauge!(
+        "db_pool_idle_connections",
+        "Open Postgres connections not currently in use."
+    );
+    metrics::describe_gauge!(
+        "delivery_queue_depth",
+        "Newsletter issue deliveries queued but not yet sent."
+    );
+    metrics::describe_gauge!(
+        "delivery_queue_oldest_age_seconds",
+        metrics::Unit::Seconds,
+        "Age of the oldest queued newsletter issue delivery."
+    );
+}
+
+/// Measures a request from the moment it reaches the middleware until its
+/// response is ready.
+pub struct HttpRequestTimer {
+    method: String,
+    route: String,
+    start: Instant,
+}
+
+impl HttpRequestTimer {
+    pub fn start(request: &ServiceRequest) -> Self {
+        Self {
+            method: request.method().to_string(),
+            // Raw paths carry ids, so requests are labelled by route
+            // pattern to keep the number of series bounded.
+            route: request
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".into()),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn finish<B>(self, response: &Result<ServiceResponse<B>, actix_web::Error>) {
+        let status = match response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        let labels = [
+            ("method", self.method),
+            ("route", self.route),
+            ("status", status.as_u16().to_string()),
+        ];
+        metrics::increment_counter!("http_requests_total", &labels);
+        metrics::histogram!(
+            "http_request_duration_seconds",
+            self.start.elapsed().as_secs_f64(),
+            &labels
+        );
+    }
+}
+
+/// Records the outcome of a single request to the email provider.
+pub fn record_email_client_request(outcome: &'static str, start: Instant) {
+    metrics::increment_counter!("email_client_requests_total", "outcome" => outcome);
+    metrics::histogram!(
+        "email_client_request_duration_seconds",
+        start.elapsed().as_secs_f64(),
+        "outcome" => outcome
+    );
+}
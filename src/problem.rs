@@ -0,0 +1,166 @@
+use crate::request_id::RequestId;
+use actix_web::http::header;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Shown instead of the details of a server error, which only go to our logs.
+const UNEXPECTED_DETAIL: &str =
+    "Something went wrong on our side. Quote the request id if you report it.";
+
+/// An RFC 7807 problem details body. Alongside the standard members it
+/// carries a stable, machine-readable `code`, the `errors` of individual
+/// fields when input failed validation, and the id of the request.
+#[derive(serde::Serialize, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    #[serde(serialize_with = "serialize_status")]
+    status: StatusCode,
+    detail: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Why the value of a single input field was rejected.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl Problem {
+    /// A problem for the request handled by the current task.
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            // Our codes are not documented at a URL of their own, which
+            // RFC 7807 spells as `about:blank`.
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status,
+            detail: detail.into(),
+            code,
+            errors: Vec::new(),
+            request_id: RequestId::current().map(|request_id| request_id.to_string()),
+        }
+    }
+
+    /// The same body for every unexpected error, whatever went wrong.
+    pub fn unexpected() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            UNEXPECTED_DETAIL,
+        )
+    }
+
+    /// A problem for errors that do not describe themselves, such as those
+    /// raised by extractors. Client errors keep their message; server
+    /// errors are reported as unexpected.
+    pub fn from_error(error: &dyn ResponseError) -> Self {
+        let status = error.status_code();
+        if status.is_server_error() {
+            Self {
+                status,
+                title: status.canonical_reason().unwrap_or("Error"),
+                ..Self::unexpected()
+            }
+        } else {
+            Self::new(status, status_code(status), error.to_string())
+        }
+    }
+
+    pub fn with_errors(mut self, errors: &[FieldError]) -> Self {
+        self.errors = errors.to_vec();
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: &RequestId) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Failed to serialize a problem")
+    }
+}
+
+impl From<Problem> for HttpResponse {
+    fn from(problem: Problem) -> Self {
+        HttpResponse::build(problem.status)
+            .insert_header((header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE))
+            .body(problem.to_json())
+    }
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
+
+/// Codes for problems raised outside of our own error types.
+fn status_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+        StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+        _ => "client_error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldError, Problem};
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("connection refused by db-primary:5432")]
+    struct DatabaseDown;
+
+    impl ResponseError for DatabaseDown {}
+
+    #[test]
+    fn problems_serialize_with_the_rfc_7807_members() {
+        let problem = Problem::new(StatusCode::BAD_REQUEST, "invalid_input", "Bad input.")
+            .with_errors(&[FieldError {
+                field: "email",
+                message: "not an email".into(),
+            }]);
+
+        let body = serde_json::to_value(&problem).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "Bad input.",
+                "code": "invalid_input",
+                "errors": [{ "field": "email", "message": "not an email" }],
+            })
+        );
+    }
+
+    #[test]
+    fn server_errors_do_not_expose_their_message() {
+        let body = serde_json::to_string(&Problem::from_error(&DatabaseDown)).unwrap();
+
+        assert!(!body.contains("db-primary"));
+        assert!(body.contains("\"code\":\"internal_error\""));
+    }
+}
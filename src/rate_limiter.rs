@@ -0,0 +1,174 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket holding up to `capacity` tokens, refilled continuously at
+/// `capacity / period`.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, period: Duration) -> Self {
+        let capacity = f64::from(capacity);
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: capacity / period.as_secs_f64(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes `tokens` if they are available, otherwise returns how long the
+    /// caller has to wait for them to be refilled.
+    ///
+    /// Asking for more than `capacity` only waits for a full bucket; the
+    /// bucket then goes into debt, which the next callers wait out.
+    fn try_acquire(&mut self, tokens: f64) -> Result<(), Duration> {
+        self.refill();
+        let needed = tokens.min(self.capacity);
+        if self.tokens >= needed {
+            self.tokens -= tokens;
+            Ok(())
+        } else {
+            let missing = needed - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_second))
+        }
+    }
+}
+
+/// Rate limiter shared by every clone of an `EmailClient`.
+///
+/// Each configured limit gets its own bucket, holding one token per email:
+/// a request is only let through once a token for each of its recipients
+/// has been taken from all of them.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Vec<Mutex<TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Builds a limiter allowing at most `max_emails` per `period` for each
+    /// `(max_emails, period)` pair.
+    pub fn new(limits: impl IntoIterator<Item = (u32, Duration)>) -> Self {
+        let buckets = limits
+            .into_iter()
+            .filter(|(max_emails, period)| *max_emails > 0 && !period.is_zero())
+            .map(|(max_emails, period)| Mutex::new(TokenBucket::new(max_emails, period)))
+            .collect();
+        Self {
+            buckets: Arc::new(buckets),
+        }
+    }
+
+    /// Waits until `emails` more emails may be sent under every limit.
+    pub async fn acquire(&self, emails: u32) {
+        for bucket in self.buckets.iter() {
+            loop {
+                let outcome = bucket.lock().unwrap().try_acquire(f64::from(emails));
+                match outcome {
+                    Ok(()) => break,
+                    Err(wait) => tokio::time::sleep(wait).await,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn an_unlimited_limiter_never_waits() {
+        let limiter = RateLimiter::unlimited();
+        let start = Instant::now();
+
+        for _ in 0..1000 {
+            limiter.acquire(1).await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let limiter = RateLimiter::new([(5, Duration::from_secs(60))]);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            limiter.acquire(1).await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn requests_above_the_limit_wait_for_a_refill() {
+        let limiter = RateLimiter::new([(10, Duration::from_secs(1))]);
+        let start = Instant::now();
+
+        // The first 10 drain the bucket, the next 5 need ~500ms of refill.
+        for _ in 0..15 {
+            limiter.acquire(1).await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn the_strictest_limit_wins() {
+        let limiter =
+            RateLimiter::new([(100, Duration::from_secs(1)), (2, Duration::from_secs(1))]);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire(1).await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn each_email_takes_a_token() {
+        let limiter = RateLimiter::new([(10, Duration::from_secs(1))]);
+        let start = Instant::now();
+
+        // A batch of 10 drains the bucket, the next 5 need ~500ms of refill.
+        limiter.acquire(10).await;
+        limiter.acquire(5).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn batches_larger_than_the_limit_are_paid_back_by_later_sends() {
+        let limiter = RateLimiter::new([(10, Duration::from_millis(100))]);
+        let start = Instant::now();
+
+        // 20 emails leave the bucket 10 short, so the next one waits for 11
+        // tokens: ~110ms at 100 a second.
+        limiter.acquire(20).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.acquire(1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
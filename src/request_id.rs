@@ -0,0 +1,174 @@
+use crate::problem::{Problem, PROBLEM_CONTENT_TYPE};
+use crate::telemetry::set_parent_from_headers;
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use std::future::Future;
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use uuid::Uuid;
+
+/// Header a request id is read from and echoed in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a caller.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Identifies a single HTTP request across our logs, responses and the
+/// calls we make to the email provider on its behalf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts an id chosen by the caller when it is short and made of
+    /// characters safe to log and send on as a header.
+    pub fn parse(value: &str) -> Option<Self> {
+        let is_valid = !value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        is_valid.then(|| Self(value.to_owned()))
+    }
+
+    /// The caller's `X-Request-Id`, if valid, or a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+            .unwrap_or_else(Self::generate)
+    }
+
+    /// The id of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Runs `f` with `self` as the current request id.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Root span for `TracingLogger`, carrying our `RequestId` rather than the
+/// one `tracing-actix-web` generates, so a caller's `X-Request-Id` is the
+/// one found in the logs. The id is stored in the request extensions for
+/// the middleware echoing it back.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = RequestId::from_headers(request.headers());
+        request.extensions_mut().insert(request_id.clone());
+
+        let method = request.method().as_str();
+        let route = request.match_pattern().unwrap_or_else(|| "default".into());
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %method,
+            http.route = %route,
+            http.target = %request.uri().path_and_query().map(|p| p.as_str()).unwrap_or(""),
+            http.client_ip = %request.connection_info().realip_remote_addr().unwrap_or(""),
+            http.status_code = tracing::field::Empty,
+            otel.name = %format!("HTTP {} {}", method, route),
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            request_id = %request_id,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        );
+        set_parent_from_headers(&span, request.headers());
+        span
+    }
+
+    fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// Echoes `request_id` in the response headers, and turns responses built
+/// from errors that do not produce problem details themselves, such as
+/// extractor errors, into a `Problem`.
+pub fn echo_request_id(
+    response: ServiceResponse<BoxBody>,
+    request_id: &RequestId,
+) -> ServiceResponse<BoxBody> {
+    let is_problem = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == PROBLEM_CONTENT_TYPE);
+    let problem = match response.response().error() {
+        Some(error) if !is_problem => {
+            Some(Problem::from_error(error.as_response_error()).with_request_id(request_id))
+        }
+        _ => None,
+    };
+    let mut response = match problem {
+        // The error stays attached for `TracingLogger` to record.
+        Some(problem) => response.map_body(|head, _| {
+            head.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+            );
+            BoxBody::new(problem.to_json())
+        }),
+        None => response,
+    };
+    response.headers_mut().insert(
+        HeaderName::from_static(REQUEST_ID_HEADER),
+        HeaderValue::from_str(request_id.as_ref()).expect("Request ids are valid header values"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+
+    #[test]
+    fn caller_ids_made_of_safe_characters_are_accepted() {
+        for id in ["abc-123", "req_1.2:3", &"a".repeat(128)] {
+            assert_eq!(RequestId::parse(id).unwrap().as_ref(), id);
+        }
+    }
+
+    #[test]
+    fn unsafe_or_oversized_caller_ids_are_rejected() {
+        for id in ["", "with space", "new\nline", "é", &"a".repeat(129)] {
+            assert!(RequestId::parse(id).is_none(), "{:?} was accepted", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_current_id_is_only_set_within_its_scope() {
+        let request_id = RequestId::generate();
+        assert_eq!(RequestId::current(), None);
+        let current = request_id
+            .clone()
+            .scope(async { RequestId::current() })
+            .await;
+        assert_eq!(current, Some(request_id));
+    }
+}
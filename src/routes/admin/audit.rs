@@ -0,0 +1,159 @@
+use crate::authentication::has_bearer_token;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::startup::AdminApiToken;
+use actix_web::{get, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(thiserror::Error)]
+pub enum AuditLogError {
+    #[error("A valid admin API token is required.")]
+    Unauthorized,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuditLogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AuditLogError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuditLogError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AuditLogError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AuditLogError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AuditLogError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            AuditLogError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_query", self.to_string())
+            }
+            AuditLogError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+/// Every filter is optional; `from` is inclusive and `to` exclusive.
+#[derive(serde::Deserialize, Debug)]
+pub struct AuditLogQuery {
+    action: Option<String>,
+    actor: Option<String>,
+    target: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct AuditLogPage {
+    page: i64,
+    page_size: i64,
+    entries: Vec<AuditLogEntry>,
+}
+
+#[derive(serde::Serialize)]
+struct AuditLogEntry {
+    id: i64,
+    occurred_at: DateTime<Utc>,
+    actor: Option<String>,
+    action: String,
+    target: Option<String>,
+    ip: Option<String>,
+    forwarded_for: Option<String>,
+    request_id: Option<String>,
+    details: serde_json::Value,
+}
+
+/// Lists audit log entries, newest first. Requires the admin API token.
+#[tracing::instrument(name = "Get audit log entries", skip(request, pool, admin_api_token))]
+#[get("/admin/audit")]
+pub async fn get_audit_log(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    query: web::Query<AuditLogQuery>,
+) -> Result<HttpResponse, AuditLogError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(AuditLogError::Unauthorized);
+    }
+    let page = query.page.unwrap_or(1);
+    let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page < 1 {
+        return Err(AuditLogError::ValidationError(
+            "`page` must be at least 1.".into(),
+        ));
+    }
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(AuditLogError::ValidationError(format!(
+            "`page_size` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return Err(AuditLogError::ValidationError(
+                "`from` must be before `to`.".into(),
+            ));
+        }
+    }
+
+    let entries = get_audit_log_page(&pool, &query, page, page_size)
+        .await
+        .context("Failed to fetch audit log entries")?;
+
+    Ok(HttpResponse::Ok().json(AuditLogPage {
+        page,
+        page_size,
+        entries,
+    }))
+}
+
+#[tracing::instrument(name = "Get a page of audit log entries", skip(pool))]
+async fn get_audit_log_page(
+    pool: &PgPool,
+    query: &AuditLogQuery,
+    page: i64,
+    page_size: i64,
+) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT id, occurred_at, actor, action, target, ip, forwarded_for, request_id, details
+        FROM audit_log
+        WHERE ($1::text IS NULL OR action = $1)
+            AND ($2::text IS NULL OR actor = $2)
+            AND ($3::text IS NULL OR target = $3)
+            AND ($4::timestamptz IS NULL OR occurred_at >= $4)
+            AND ($5::timestamptz IS NULL OR occurred_at < $5)
+        ORDER BY occurred_at DESC, id DESC
+        LIMIT $6 OFFSET $7
+        "#,
+        query.action,
+        query.actor,
+        query.target,
+        query.from,
+        query.to,
+        page_size,
+        (page - 1) * page_size,
+    )
+    .fetch_all(pool)
+    .await
+}
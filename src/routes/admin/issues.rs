@@ -0,0 +1,338 @@
+use crate::authentication::has_bearer_token;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::startup::AdminApiToken;
+use actix_web::{get, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(thiserror::Error)]
+pub enum IssueReportError {
+    #[error("A valid admin API token is required.")]
+    Unauthorized,
+    #[error("There is no newsletter issue with the provided id.")]
+    NotFound,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssueReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for IssueReportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IssueReportError::Unauthorized => StatusCode::UNAUTHORIZED,
+            IssueReportError::NotFound => StatusCode::NOT_FOUND,
+            IssueReportError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            IssueReportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            IssueReportError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            IssueReportError::NotFound => {
+                Problem::new(self.status_code(), "issue_not_found", self.to_string())
+            }
+            IssueReportError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_query", self.to_string())
+            }
+            IssueReportError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct DeliveriesQuery {
+    status: Option<String>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryReport {
+    newsletter_issue_id: Uuid,
+    /// Number of deliveries per status, plus their `total`.
+    counts: BTreeMap<String, i64>,
+    page: i64,
+    page_size: i64,
+    deliveries: Vec<Delivery>,
+}
+
+#[derive(serde::Serialize)]
+struct Delivery {
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    status: String,
+    provider_message_id: Option<String>,
+    failure_reason: Option<String>,
+    /// The `X-Request-Id` of the publish request that queued the delivery.
+    request_id: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Get newsletter issue deliveries",
+    skip(request, pool, admin_api_token)
+)]
+#[get("/admin/issues/{newsletter_issue_id}/deliveries")]
+pub async fn get_issue_deliveries(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<DeliveriesQuery>,
+) -> Result<HttpResponse, IssueReportError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(IssueReportError::Unauthorized);
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let page = query.page.unwrap_or(1);
+    let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page < 1 {
+        return Err(IssueReportError::ValidationError(
+            "`page` must be at least 1.".into(),
+        ));
+    }
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(IssueReportError::ValidationError(format!(
+            "`page_size` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    ensure_issue_exists(&pool, newsletter_issue_id).await?;
+
+    let counts = count_deliveries_by_status(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to count newsletter issue deliveries")?;
+    let deliveries = get_deliveries_page(
+        &pool,
+        newsletter_issue_id,
+        query.status.as_deref(),
+        page,
+        page_size,
+    )
+    .await
+    .context("Failed to fetch newsletter issue deliveries")?;
+
+    Ok(HttpResponse::Ok().json(DeliveryReport {
+        newsletter_issue_id,
+        counts,
+        page,
+        page_size,
+        deliveries,
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct OpenReport {
+    newsletter_issue_id: Uuid,
+    track_opens: bool,
+    /// Every recorded pixel load, including repeated opens.
+    total_opens: i64,
+    /// Distinct subscribers who opened the issue at least once.
+    unique_opens: i64,
+}
+
+#[tracing::instrument(
+    name = "Get newsletter issue opens",
+    skip(request, pool, admin_api_token)
+)]
+#[get("/admin/issues/{newsletter_issue_id}/opens")]
+pub async fn get_issue_opens(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, IssueReportError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(IssueReportError::Unauthorized);
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let report = sqlx::query_as!(
+        OpenReport,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.track_opens,
+            COUNT(o.subscriber_id) AS "total_opens!",
+            COUNT(DISTINCT o.subscriber_id) AS "unique_opens!"
+        FROM newsletter_issues i
+        LEFT JOIN open_events o USING (newsletter_issue_id)
+        WHERE i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to count newsletter issue opens")?
+    .ok_or(IssueReportError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(serde::Serialize)]
+struct ClickReport {
+    newsletter_issue_id: Uuid,
+    total_clicks: i64,
+    unique_clicks: i64,
+    /// Clicks per destination, most clicked first.
+    links: Vec<LinkClicks>,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct LinkClicks {
+    pub url: String,
+    pub total_clicks: i64,
+    /// Distinct subscribers who clicked this link.
+    pub unique_clicks: i64,
+}
+
+#[tracing::instrument(
+    name = "Get newsletter issue clicks",
+    skip(request, pool, admin_api_token)
+)]
+#[get("/admin/issues/{newsletter_issue_id}/clicks")]
+pub async fn get_issue_clicks(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, IssueReportError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(IssueReportError::Unauthorized);
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    ensure_issue_exists(&pool, newsletter_issue_id).await?;
+
+    let links = get_link_clicks(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to count clicks per link")?;
+    let unique_clicks = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT subscriber_id) AS "count!"
+        FROM click_events
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count unique clicks")?
+    .count;
+
+    Ok(HttpResponse::Ok().json(ClickReport {
+        newsletter_issue_id,
+        total_clicks: links.iter().map(|l| l.total_clicks).sum(),
+        unique_clicks,
+        links,
+    }))
+}
+
+/// Clicks per destination, most clicked first.
+#[tracing::instrument(name = "Count newsletter issue clicks per link", skip(pool))]
+pub(super) async fn get_link_clicks(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT
+            url,
+            COUNT(*) AS "total_clicks!",
+            COUNT(DISTINCT subscriber_id) AS "unique_clicks!"
+        FROM click_events
+        WHERE newsletter_issue_id = $1
+        GROUP BY url
+        ORDER BY 2 DESC, url
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Check newsletter issue exists", skip(pool))]
+pub async fn ensure_issue_exists(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<(), IssueReportError> {
+    sqlx::query!(
+        r#"SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the newsletter issue")?
+    .ok_or(IssueReportError::NotFound)?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Count newsletter issue deliveries", skip(pool))]
+async fn count_deliveries_by_status(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<BTreeMap<String, i64>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        GROUP BY status
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut counts: BTreeMap<_, _> = rows.into_iter().map(|r| (r.status, r.count)).collect();
+    let total = counts.values().sum();
+    counts.insert("total".into(), total);
+    Ok(counts)
+}
+
+#[tracing::instrument(name = "Get a page of newsletter issue deliveries", skip(pool))]
+async fn get_deliveries_page(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    status: Option<&str>,
+    page: i64,
+    page_size: i64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT subscriber_id, subscriber_email, status, provider_message_id,
+            failure_reason, request_id, updated_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND ($2::text IS NULL OR status = $2)
+        ORDER BY subscriber_email, subscriber_id
+        LIMIT $3 OFFSET $4
+        "#,
+        newsletter_issue_id,
+        status,
+        page_size,
+        (page - 1) * page_size,
+    )
+    .fetch_all(pool)
+    .await
+}
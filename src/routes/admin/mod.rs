@@ -0,0 +1,13 @@
+mod audit;
+mod issues;
+mod reports;
+mod stats;
+mod subscribers;
+mod suppressions;
+
+pub use audit::*;
+pub use issues::*;
+pub use reports::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use suppressions::*;
@@ -0,0 +1,240 @@
+use super::stats::rate;
+use crate::authentication::has_bearer_token;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::startup::AdminApiToken;
+use actix_web::{get, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+/// Length of the default reporting window, ending today.
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+/// Longest range a single report may cover.
+const MAX_RANGE_DAYS: i64 = 5 * 366;
+
+#[derive(thiserror::Error)]
+pub enum ReportError {
+    #[error("A valid admin API token is required.")]
+    Unauthorized,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ReportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ReportError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ReportError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ReportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ReportError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            ReportError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_query", self.to_string())
+            }
+            ReportError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscriberReportQuery {
+    /// First day of the report, inclusive.
+    from: Option<NaiveDate>,
+    /// Last day of the report, inclusive.
+    to: Option<NaiveDate>,
+    bucket: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberReport {
+    from: NaiveDate,
+    to: NaiveDate,
+    bucket: &'static str,
+    totals: GrowthCounts,
+    buckets: Vec<GrowthBucket>,
+}
+
+#[derive(serde::Serialize)]
+struct GrowthBucket {
+    /// Start of the calendar day, week (from Monday) or month, in UTC.
+    bucket_start: NaiveDateTime,
+    #[serde(flatten)]
+    counts: GrowthCounts,
+}
+
+#[derive(serde::Serialize, Default)]
+struct GrowthCounts {
+    signups: i64,
+    confirmations: i64,
+    /// Share of this period's signups who have confirmed since.
+    confirmation_rate: f64,
+    unsubscribes: i64,
+    /// Confirmed subscribers lost to unsubscribes, bounces or complaints.
+    churned: i64,
+    /// `confirmations - churned`: the change in confirmed subscribers.
+    net_growth: i64,
+}
+
+struct GrowthRow {
+    bucket_start: NaiveDateTime,
+    signups: i64,
+    confirmed_signups: i64,
+    confirmations: i64,
+    unsubscribes: i64,
+    churned: i64,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber growth report",
+    skip(request, pool, admin_api_token)
+)]
+#[get("/admin/reports/subscribers")]
+pub async fn get_subscriber_report(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    query: web::Query<SubscriberReportQuery>,
+) -> Result<HttpResponse, ReportError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(ReportError::Unauthorized);
+    }
+    let to = query.to.unwrap_or_else(|| Utc::today().naive_utc());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Duration::days(DEFAULT_RANGE_DAYS - 1));
+    let bucket = match query.bucket.as_deref() {
+        None | Some("day") => "day",
+        Some("week") => "week",
+        Some("month") => "month",
+        Some(_) => {
+            return Err(ReportError::ValidationError(
+                "`bucket` must be one of `day`, `week` or `month`.".into(),
+            ))
+        }
+    };
+    if from > to {
+        return Err(ReportError::ValidationError(
+            "`from` must not be after `to`.".into(),
+        ));
+    }
+    if (to - from).num_days() >= MAX_RANGE_DAYS {
+        return Err(ReportError::ValidationError(format!(
+            "The report cannot span more than {} days.",
+            MAX_RANGE_DAYS
+        )));
+    }
+
+    let rows = get_growth_rows(&pool, from, to, bucket)
+        .await
+        .context("Failed to compute subscriber growth")?;
+
+    let mut totals = GrowthCounts::default();
+    let mut total_confirmed_signups = 0;
+    let buckets = rows
+        .into_iter()
+        .map(|row| {
+            totals.signups += row.signups;
+            totals.confirmations += row.confirmations;
+            totals.unsubscribes += row.unsubscribes;
+            totals.churned += row.churned;
+            total_confirmed_signups += row.confirmed_signups;
+            GrowthBucket {
+                bucket_start: row.bucket_start,
+                counts: GrowthCounts {
+                    signups: row.signups,
+                    confirmations: row.confirmations,
+                    confirmation_rate: rate(row.confirmed_signups, row.signups),
+                    unsubscribes: row.unsubscribes,
+                    churned: row.churned,
+                    net_growth: row.confirmations - row.churned,
+                },
+            }
+        })
+        .collect();
+    totals.confirmation_rate = rate(total_confirmed_signups, totals.signups);
+    totals.net_growth = totals.confirmations - totals.churned;
+
+    Ok(HttpResponse::Ok().json(SubscriberReport {
+        from,
+        to,
+        bucket,
+        totals,
+        buckets,
+    }))
+}
+
+/// One row per calendar `bucket` overlapping `from..=to`, in UTC. Buckets
+/// are whole periods, so the first and last ones may extend past the range.
+#[tracing::instrument(name = "Compute subscriber growth per bucket", skip(pool))]
+async fn get_growth_rows(
+    pool: &PgPool,
+    from: NaiveDate,
+    to: NaiveDate,
+    bucket: &str,
+) -> Result<Vec<GrowthRow>, sqlx::Error> {
+    sqlx::query_as!(
+        GrowthRow,
+        r#"
+        WITH buckets AS (
+            SELECT
+                b AS bucket_start,
+                b AT TIME ZONE 'UTC' AS starts_at,
+                (b + ('1 ' || $3)::interval) AT TIME ZONE 'UTC' AS ends_at
+            FROM generate_series(
+                date_trunc($3, $1::date::timestamp),
+                date_trunc($3, $2::date::timestamp),
+                ('1 ' || $3)::interval
+            ) AS b
+        )
+        SELECT
+            b.bucket_start AS "bucket_start!",
+            (SELECT COUNT(*) FROM subscriptions s
+                WHERE s.subscribed_at >= b.starts_at AND s.subscribed_at < b.ends_at)
+                AS "signups!",
+            (SELECT COUNT(*) FROM subscriptions s
+                WHERE s.subscribed_at >= b.starts_at AND s.subscribed_at < b.ends_at
+                AND EXISTS (
+                    SELECT 1 FROM subscription_status_changes c
+                    WHERE c.subscriber_id = s.id AND c.to_status = 'confirmed'
+                ))
+                AS "confirmed_signups!",
+            (SELECT COUNT(*) FROM subscription_status_changes c
+                WHERE c.changed_at >= b.starts_at AND c.changed_at < b.ends_at
+                AND c.to_status = 'confirmed')
+                AS "confirmations!",
+            (SELECT COUNT(*) FROM subscription_status_changes c
+                WHERE c.changed_at >= b.starts_at AND c.changed_at < b.ends_at
+                AND c.to_status = 'unsubscribed')
+                AS "unsubscribes!",
+            (SELECT COUNT(*) FROM subscription_status_changes c
+                WHERE c.changed_at >= b.starts_at AND c.changed_at < b.ends_at
+                AND c.from_status = 'confirmed')
+                AS "churned!"
+        FROM buckets b
+        ORDER BY b.bucket_start
+        "#,
+        from,
+        to,
+        bucket,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,326 @@
+use super::issues::{ensure_issue_exists, get_link_clicks, IssueReportError, LinkClicks};
+use crate::authentication::has_bearer_token;
+use crate::startup::AdminApiToken;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Number of links listed under `top_links`.
+const TOP_LINKS: usize = 10;
+
+/// Delivery statuses meaning the provider accepted the email.
+const SENT_STATUSES: &[&str] = &["sent", "delivered", "bounced", "soft_bounced", "complained"];
+
+#[derive(serde::Deserialize, Debug)]
+pub struct StatsQuery {
+    bucket: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct IssueStats {
+    newsletter_issue_id: Uuid,
+    counts: StatsCounts,
+    rates: StatsRates,
+    bucket: &'static str,
+    timeline: Vec<TimelineBucket>,
+    top_links: Vec<LinkClicks>,
+}
+
+/// Opens, clicks and unsubscribes count subscribers, not events.
+#[derive(serde::Serialize)]
+struct StatsCounts {
+    sent: i64,
+    delivered: i64,
+    bounced: i64,
+    complained: i64,
+    opened: i64,
+    clicked: i64,
+    unsubscribed: i64,
+}
+
+/// Every rate is a fraction of `sent`, except `click_to_open_rate` which is
+/// a fraction of `opened`.
+#[derive(serde::Serialize)]
+struct StatsRates {
+    delivery_rate: f64,
+    bounce_rate: f64,
+    complaint_rate: f64,
+    open_rate: f64,
+    click_rate: f64,
+    click_to_open_rate: f64,
+    unsubscribe_rate: f64,
+}
+
+/// Buckets without any open or click are omitted.
+#[derive(serde::Serialize)]
+struct TimelineBucket {
+    bucket_start: DateTime<Utc>,
+    opens: i64,
+    clicks: i64,
+}
+
+#[tracing::instrument(
+    name = "Get newsletter issue stats",
+    skip(request, pool, admin_api_token)
+)]
+#[get("/admin/issues/{newsletter_issue_id}/stats")]
+pub async fn get_issue_stats(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, IssueReportError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(IssueReportError::Unauthorized);
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let bucket = match query.bucket.as_deref() {
+        None | Some("hour") => "hour",
+        Some("day") => "day",
+        Some(_) => {
+            return Err(IssueReportError::ValidationError(
+                "`bucket` must be either `hour` or `day`.".into(),
+            ))
+        }
+    };
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(_) => {
+            return Err(IssueReportError::ValidationError(
+                "`format` must be either `json` or `csv`.".into(),
+            ))
+        }
+    };
+
+    ensure_issue_exists(&pool, newsletter_issue_id).await?;
+
+    let counts = get_stats_counts(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to count newsletter issue outcomes")?;
+    let timeline = get_timeline(&pool, newsletter_issue_id, bucket)
+        .await
+        .context("Failed to bucket newsletter issue opens and clicks")?;
+    let mut top_links = get_link_clicks(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to count clicks per link")?;
+    top_links.truncate(TOP_LINKS);
+
+    let stats = IssueStats {
+        newsletter_issue_id,
+        rates: StatsRates::from(&counts),
+        counts,
+        bucket,
+        timeline,
+        top_links,
+    };
+
+    if csv {
+        Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"issue-{}-stats.csv\"",
+                    newsletter_issue_id
+                ),
+            ))
+            .body(stats.to_csv()))
+    } else {
+        Ok(HttpResponse::Ok().json(stats))
+    }
+}
+
+impl From<&StatsCounts> for StatsRates {
+    fn from(counts: &StatsCounts) -> Self {
+        Self {
+            delivery_rate: rate(counts.delivered, counts.sent),
+            bounce_rate: rate(counts.bounced, counts.sent),
+            complaint_rate: rate(counts.complained, counts.sent),
+            open_rate: rate(counts.opened, counts.sent),
+            click_rate: rate(counts.clicked, counts.sent),
+            click_to_open_rate: rate(counts.clicked, counts.opened),
+            unsubscribe_rate: rate(counts.unsubscribed, counts.sent),
+        }
+    }
+}
+
+/// `count / total`, or zero when there is nothing to divide by.
+pub(super) fn rate(count: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+impl IssueStats {
+    /// Renders the stats in long format, one `section,key,metric,value`
+    /// row per number, so every section fits a single table.
+    fn to_csv(&self) -> String {
+        let mut rows = vec![["section", "key", "metric", "value"].map(String::from)];
+        let counts = &self.counts;
+        for (metric, value) in [
+            ("sent", counts.sent),
+            ("delivered", counts.delivered),
+            ("bounced", counts.bounced),
+            ("complained", counts.complained),
+            ("opened", counts.opened),
+            ("clicked", counts.clicked),
+            ("unsubscribed", counts.unsubscribed),
+        ] {
+            rows.push(["count".into(), "".into(), metric.into(), value.to_string()]);
+        }
+        let rates = &self.rates;
+        for (metric, value) in [
+            ("delivery_rate", rates.delivery_rate),
+            ("bounce_rate", rates.bounce_rate),
+            ("complaint_rate", rates.complaint_rate),
+            ("open_rate", rates.open_rate),
+            ("click_rate", rates.click_rate),
+            ("click_to_open_rate", rates.click_to_open_rate),
+            ("unsubscribe_rate", rates.unsubscribe_rate),
+        ] {
+            rows.push(["rate".into(), "".into(), metric.into(), value.to_string()]);
+        }
+        for bucket in &self.timeline {
+            let start = bucket
+                .bucket_start
+                .to_rfc3339_opts(SecondsFormat::Secs, true);
+            rows.push([
+                "timeline".into(),
+                start.clone(),
+                "opens".into(),
+                bucket.opens.to_string(),
+            ]);
+            rows.push([
+                "timeline".into(),
+                start,
+                "clicks".into(),
+                bucket.clicks.to_string(),
+            ]);
+        }
+        for link in &self.top_links {
+            rows.push([
+                "link".into(),
+                link.url.clone(),
+                "total_clicks".into(),
+                link.total_clicks.to_string(),
+            ]);
+            rows.push([
+                "link".into(),
+                link.url.clone(),
+                "unique_clicks".into(),
+                link.unique_clicks.to_string(),
+            ]);
+        }
+
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|field| csv_field(field))
+                    .collect::<Vec<_>>()
+                    .join(",")
+                    + "\r\n"
+            })
+            .collect()
+    }
+}
+
+/// Quotes a CSV field when it contains a delimiter, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[tracing::instrument(name = "Count newsletter issue outcomes", skip(pool))]
+async fn get_stats_counts(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<StatsCounts, sqlx::Error> {
+    let sent_statuses: Vec<String> = SENT_STATUSES.iter().map(|s| s.to_string()).collect();
+    sqlx::query_as!(
+        StatsCounts,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status = ANY($2)) AS "sent!",
+            (SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status IN ('delivered', 'complained'))
+                AS "delivered!",
+            (SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status IN ('bounced', 'soft_bounced'))
+                AS "bounced!",
+            (SELECT COUNT(*) FROM issue_deliveries
+                WHERE newsletter_issue_id = $1 AND status = 'complained') AS "complained!",
+            (SELECT COUNT(DISTINCT subscriber_id) FROM open_events
+                WHERE newsletter_issue_id = $1) AS "opened!",
+            (SELECT COUNT(DISTINCT subscriber_id) FROM click_events
+                WHERE newsletter_issue_id = $1) AS "clicked!",
+            (SELECT COUNT(DISTINCT lower(email)) FROM email_events
+                WHERE newsletter_issue_id = $1 AND event = 'unsubscribed') AS "unsubscribed!"
+        "#,
+        newsletter_issue_id,
+        &sent_statuses,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Bucket newsletter issue opens and clicks", skip(pool))]
+async fn get_timeline(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    bucket: &str,
+) -> Result<Vec<TimelineBucket>, sqlx::Error> {
+    sqlx::query_as!(
+        TimelineBucket,
+        r#"
+        SELECT
+            date_trunc($2, t.at) AS "bucket_start!",
+            COUNT(*) FILTER (WHERE t.kind = 'open') AS "opens!",
+            COUNT(*) FILTER (WHERE t.kind = 'click') AS "clicks!"
+        FROM (
+            SELECT opened_at AS at, 'open' AS kind FROM open_events
+            WHERE newsletter_issue_id = $1
+            UNION ALL
+            SELECT clicked_at AS at, 'click' AS kind FROM click_events
+            WHERE newsletter_issue_id = $1
+        ) AS t
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        newsletter_issue_id,
+        bucket,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, rate};
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(
+            csv_field("https://example.com/?a=1,2"),
+            "\"https://example.com/?a=1,2\""
+        );
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn rates_of_an_empty_total_are_zero() {
+        assert_eq!(rate(0, 0), 0.0);
+        assert_eq!(rate(1, 4), 0.25);
+    }
+}
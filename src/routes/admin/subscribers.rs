@@ -0,0 +1,123 @@
+use crate::audit::{record_audit_event, AuditActor};
+use crate::authentication::has_bearer_token;
+use crate::data_export::export_subscriber_data;
+use crate::erasure::erase_subscriber;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::export_attachment;
+use crate::startup::AdminApiToken;
+use actix_web::{delete, get, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum SubscriberAdminError {
+    #[error("A valid admin API token is required.")]
+    Unauthorized,
+    #[error("There is no subscriber with the provided id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberAdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberAdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberAdminError::Unauthorized => StatusCode::UNAUTHORIZED,
+            SubscriberAdminError::NotFound => StatusCode::NOT_FOUND,
+            SubscriberAdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscriberAdminError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            SubscriberAdminError::NotFound => {
+                Problem::new(self.status_code(), "subscriber_not_found", self.to_string())
+            }
+            SubscriberAdminError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+/// Everything held about a subscriber, e.g. to answer an access request
+/// received by other means than the emailed link. Requires the admin API
+/// token.
+#[tracing::instrument(
+    name = "Export a subscriber's data for an admin",
+    skip(request, pool, admin_api_token, actor)
+)]
+#[get("/admin/subscribers/{subscriber_id}/export")]
+pub async fn export_subscriber(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    subscriber_id: web::Path<Uuid>,
+    actor: AuditActor,
+) -> Result<HttpResponse, SubscriberAdminError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(SubscriberAdminError::Unauthorized);
+    }
+    let subscriber_id = subscriber_id.into_inner();
+    let export = export_subscriber_data(&pool, subscriber_id)
+        .await
+        .context("Failed to export the subscriber's data")?
+        .ok_or(SubscriberAdminError::NotFound)?;
+    record_audit_event(
+        pool.get_ref(),
+        &actor,
+        "subscriber.exported",
+        Some(&subscriber_id.to_string()),
+        serde_json::json!({}),
+    )
+    .await
+    .context("Failed to record the export in the audit log")?;
+
+    Ok(export_attachment(&export))
+}
+
+/// Erases a subscriber, e.g. to answer a right-to-erasure request. Their
+/// deliveries and tracking events are kept, anonymised, so issue stats do
+/// not change. Requires the admin API token.
+#[tracing::instrument(
+    name = "Erase a subscriber",
+    skip(request, pool, admin_api_token, actor)
+)]
+#[delete("/admin/subscribers/{subscriber_id}")]
+pub async fn erase_subscriber_data(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    subscriber_id: web::Path<Uuid>,
+    actor: AuditActor,
+) -> Result<HttpResponse, SubscriberAdminError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(SubscriberAdminError::Unauthorized);
+    }
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let erasure = erase_subscriber(&mut transaction, &actor, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber")?
+        .ok_or(SubscriberAdminError::NotFound)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase the subscriber.")?;
+
+    Ok(HttpResponse::Ok().json(erasure))
+}
@@ -0,0 +1,245 @@
+use crate::audit::{record_audit_event, AuditActor};
+use crate::authentication::has_bearer_token;
+use crate::domain::SubscriberEmail;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::startup::AdminApiToken;
+use crate::suppression::{add_suppression, email_hash, remove_suppression, SuppressionReason};
+use actix_web::{delete, post, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+/// Source recorded for suppressions added through the admin API when the
+/// caller does not provide one.
+const DEFAULT_SOURCE: &str = "admin";
+
+/// Upper bound on the number of entries accepted by a single import.
+const MAX_IMPORT_SIZE: usize = 10_000;
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("A valid admin API token is required.")]
+    Unauthorized,
+    #[error("The address is not on the suppression list.")]
+    NotFound,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SuppressionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SuppressionError::Unauthorized => StatusCode::UNAUTHORIZED,
+            SuppressionError::NotFound => StatusCode::NOT_FOUND,
+            SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SuppressionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SuppressionError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            SuppressionError::NotFound => Problem::new(
+                self.status_code(),
+                "suppression_not_found",
+                self.to_string(),
+            ),
+            SuppressionError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_suppression", self.to_string())
+            }
+            SuppressionError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct NewSuppression {
+    email: String,
+    reason: SuppressionReason,
+    source: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SuppressionImport {
+    suppressions: Vec<NewSuppression>,
+}
+
+#[derive(serde::Serialize)]
+struct ImportSummary {
+    added: usize,
+    already_suppressed: usize,
+}
+
+#[tracing::instrument(
+    name = "Add a suppression",
+    skip(request, pool, admin_api_token, body, actor)
+)]
+#[post("/admin/suppressions")]
+pub async fn create_suppression(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    body: web::Json<NewSuppression>,
+    actor: AuditActor,
+) -> Result<HttpResponse, SuppressionError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(SuppressionError::Unauthorized);
+    }
+    let email =
+        SubscriberEmail::parse(body.email.clone()).map_err(SuppressionError::ValidationError)?;
+    let source = body.source.as_deref().unwrap_or(DEFAULT_SOURCE);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let added = add_suppression(&mut transaction, email.as_ref(), body.reason, source)
+        .await
+        .context("Failed to add the address to the suppression list")?;
+    record_audit_event(
+        &mut transaction,
+        &actor,
+        "suppression.added",
+        Some(&email_hash(email.as_ref())),
+        serde_json::json!({
+            "reason": body.reason,
+            "source": source,
+            "already_suppressed": !added,
+        }),
+    )
+    .await
+    .context("Failed to record the suppression in the audit log")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to add a suppression.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Remove a suppression",
+    skip(request, pool, admin_api_token, email, actor)
+)]
+#[delete("/admin/suppressions/{email}")]
+pub async fn delete_suppression(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    email: web::Path<String>,
+    actor: AuditActor,
+) -> Result<HttpResponse, SuppressionError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(SuppressionError::Unauthorized);
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let removed = remove_suppression(&mut transaction, &email)
+        .await
+        .context("Failed to remove the address from the suppression list")?;
+    if !removed {
+        return Err(SuppressionError::NotFound);
+    }
+    record_audit_event(
+        &mut transaction,
+        &actor,
+        "suppression.removed",
+        Some(&email_hash(&email)),
+        serde_json::json!({}),
+    )
+    .await
+    .context("Failed to record the suppression removal in the audit log")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to remove a suppression.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Adds every entry in a single transaction: either the whole import is
+/// applied or, if any address is invalid, none of it is.
+#[tracing::instrument(
+    name = "Import suppressions",
+    skip(request, pool, admin_api_token, body, actor),
+    fields(entries = body.suppressions.len())
+)]
+#[post("/admin/suppressions/import")]
+pub async fn import_suppressions(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    admin_api_token: web::Data<AdminApiToken>,
+    body: web::Json<SuppressionImport>,
+    actor: AuditActor,
+) -> Result<HttpResponse, SuppressionError> {
+    if !has_bearer_token(&request, &admin_api_token.0) {
+        return Err(SuppressionError::Unauthorized);
+    }
+    if body.suppressions.len() > MAX_IMPORT_SIZE {
+        return Err(SuppressionError::ValidationError(format!(
+            "An import cannot contain more than {} suppressions.",
+            MAX_IMPORT_SIZE
+        )));
+    }
+
+    let entries = body
+        .suppressions
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            SubscriberEmail::parse(entry.email.clone())
+                .map(|email| (email, entry))
+                .map_err(|e| SuppressionError::ValidationError(format!("Entry {}: {}", index, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut added = 0;
+    for (email, entry) in &entries {
+        let inserted = add_suppression(
+            &mut transaction,
+            email.as_ref(),
+            entry.reason,
+            entry.source.as_deref().unwrap_or(DEFAULT_SOURCE),
+        )
+        .await
+        .context("Failed to add an address to the suppression list")?;
+        if inserted {
+            added += 1;
+        }
+    }
+    record_audit_event(
+        &mut transaction,
+        &actor,
+        "suppressions.imported",
+        None,
+        serde_json::json!({ "entries": entries.len(), "added": added }),
+    )
+    .await
+    .context("Failed to record the suppression import in the audit log")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import suppressions.")?;
+
+    Ok(HttpResponse::Ok().json(ImportSummary {
+        added,
+        already_suppressed: entries.len() - added,
+    }))
+}
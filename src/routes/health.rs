@@ -0,0 +1,156 @@
+use crate::configuration::{EmailProviderCheck, HealthSettings};
+use crate::email_client::EmailClient;
+use crate::migrations::MIGRATOR;
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::future::Future;
+
+/// Liveness: the process is up and serving requests.
+#[get("/health_check")]
+async fn health_check() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: ReadinessChecks,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessChecks {
+    database: Check,
+    migrations: Check,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_provider: Option<Check>,
+}
+
+#[derive(serde::Serialize)]
+struct Check {
+    healthy: bool,
+    /// `ok`, `unavailable` or `timed_out`. Why a check failed is logged
+    /// rather than answered, as errors can name hosts, users or schema.
+    status: &'static str,
+    /// Whether a failure of this check makes the instance not ready.
+    required: bool,
+    elapsed_ms: u64,
+}
+
+impl Check {
+    fn is_failing(&self) -> bool {
+        self.required && !self.healthy
+    }
+}
+
+/// Readiness: the instance can serve traffic, i.e. Postgres answers, its
+/// schema is up to date and, if configured, the email provider is
+/// reachable. Answers 503 when a required check fails.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+#[get("/health/ready")]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.check_timeout();
+    let email_provider = async {
+        match settings.email_provider_check {
+            EmailProviderCheck::Disabled => None,
+            check => Some(
+                run_check(
+                    "email_provider",
+                    check == EmailProviderCheck::Required,
+                    timeout,
+                    async { Ok(email_client.check_reachable().await?) },
+                )
+                .await,
+            ),
+        }
+    };
+    let (database, migrations, email_provider) = tokio::join!(
+        run_check("database", true, timeout, ping_database(&pool)),
+        run_check("migrations", true, timeout, check_migrations(&pool)),
+        email_provider,
+    );
+    let checks = ReadinessChecks {
+        database,
+        migrations,
+        email_provider,
+    };
+    let ready = !(checks.database.is_failing()
+        || checks.migrations.is_failing()
+        || checks
+            .email_provider
+            .as_ref()
+            .is_some_and(Check::is_failing));
+
+    let mut response = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(Readiness { ready, checks })
+}
+
+async fn run_check(
+    name: &'static str,
+    required: bool,
+    timeout: std::time::Duration,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> Check {
+    let start = std::time::Instant::now();
+    let status = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(())) => "ok",
+        Ok(Err(e)) => {
+            tracing::warn!(check = name, error.cause_chain = ?e, "A readiness check failed");
+            "unavailable"
+        }
+        Err(_) => {
+            tracing::warn!(
+                check = name,
+                timeout_ms = timeout.as_millis() as u64,
+                "A readiness check timed out"
+            );
+            "timed_out"
+        }
+    };
+    Check {
+        healthy: status == "ok",
+        status,
+        required,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+    }
+}
+
+async fn ping_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS ping")
+        .fetch_one(pool)
+        .await
+        .context("Failed to ping the database")?;
+    Ok(())
+}
+
+/// Fails if a migration embedded in the binary has not been applied
+/// successfully.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: HashSet<i64> =
+        sqlx::query!(r#"SELECT version FROM _sqlx_migrations WHERE success ORDER BY version"#)
+            .fetch_all(pool)
+            .await
+            .context("Failed to fetch the applied migrations")?
+            .into_iter()
+            .map(|r| r.version)
+            .collect();
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        anyhow::bail!("Pending migrations: {}", pending.join(", "))
+    }
+}
@@ -1,9 +1,19 @@
-mod health_check;
+mod admin;
+mod health;
+mod monitoring;
 mod newsletter;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_export;
+mod tracking;
+mod webhooks;
 
-pub use health_check::*;
+pub use admin::*;
+pub use health::*;
+pub use monitoring::*;
 pub use newsletter::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_export::*;
+pub use tracking::*;
+pub use webhooks::*;
@@ -0,0 +1,53 @@
+use crate::monitoring::prometheus_handle;
+use actix_web::{get, web, HttpResponse};
+use sqlx::PgPool;
+
+struct DeliveryQueue {
+    depth: i64,
+    oldest_age_seconds: Option<f64>,
+}
+
+/// Serves every metric in the Prometheus text format.
+///
+/// Pool and queue gauges are sampled on scrape rather than kept up to date
+/// as they change.
+#[get("/metrics")]
+pub async fn export_metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    metrics::gauge!("db_pool_connections", pool.size() as f64);
+    metrics::gauge!("db_pool_idle_connections", pool.num_idle() as f64);
+    match get_delivery_queue(&pool).await {
+        Ok(queue) => {
+            metrics::gauge!("delivery_queue_depth", queue.depth as f64);
+            metrics::gauge!(
+                "delivery_queue_oldest_age_seconds",
+                queue.oldest_age_seconds.unwrap_or(0.0)
+            );
+        }
+        // Still serve the other metrics: the scrape is how we find out the
+        // database is in trouble.
+        Err(e) => tracing::warn!(
+            error.cause_chain = ?e,
+            "Failed to sample the delivery queue"
+        ),
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(prometheus_handle().render())
+}
+
+#[tracing::instrument(name = "Sample the delivery queue", skip(pool))]
+async fn get_delivery_queue(pool: &PgPool) -> Result<DeliveryQueue, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryQueue,
+        r#"
+        SELECT
+            COUNT(*) AS "depth!",
+            EXTRACT(EPOCH FROM now() - MIN(created_at))::float8 AS oldest_age_seconds
+        FROM issue_deliveries
+        WHERE status = 'queued'
+        "#
+    )
+    .fetch_one(pool)
+    .await
+}
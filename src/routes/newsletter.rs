@@ -1,26 +1,76 @@
-use crate::{domain::SubscriberEmail, email_client::EmailClient, helper::error_chain_fmt};
+use crate::audit::{record_audit_event, AuditActor};
+use crate::email_client::{BatchRecipient, EmailClient};
+use crate::problem::Problem;
+use crate::request_id::RequestId;
+use crate::startup::{ApplicationBaseUrl, DeliveryAbort};
+use crate::tracking::{IssueRenderer, Tracking};
+use crate::{domain::SubscriberEmail, helper::error_chain_fmt};
 use actix_web::{post, web, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use reqwest::StatusCode;
 use serde::Deserialize;
 use sqlx::PgPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub html: String,
+    pub text: String,
 }
 
 #[derive(Deserialize)]
 pub struct BodyData {
-    title: String,
-    content: Content,
+    pub title: String,
+    pub content: Content,
+    /// Embed an open tracking pixel, if open tracking is enabled globally.
+    #[serde(default)]
+    pub track_opens: bool,
+    /// Route links through the click tracking redirect, if click tracking is
+    /// enabled globally.
+    #[serde(default)]
+    pub track_clicks: bool,
+}
+
+/// The outcome of publishing an issue. Deliveries that failed are left for
+/// `queue retry-failed`: publishing again would send a second issue.
+#[derive(serde::Serialize)]
+pub struct PublishedIssue {
+    pub newsletter_issue_id: Uuid,
+    /// `sent`, or `partial` if some deliveries failed.
+    pub status: &'static str,
+    #[serde(flatten)]
+    pub deliveries: DeliverySummary,
+}
+
+/// How many subscribers `deliver_issue` sent the issue to, failed to send it
+/// to and skipped.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct DeliverySummary {
+    pub sent: usize,
+    pub failed: usize,
+    pub skipped: usize,
 }
 
 pub struct ConfirmedSubscriber {
+    id: Uuid,
     email: SubscriberEmail,
 }
 
+/// A confirmed subscriber who must not be mailed: either their stored email
+/// no longer parses or their address is on the suppression list.
+#[derive(thiserror::Error, Debug)]
+#[error("{reason}")]
+pub struct SkippedSubscriber {
+    id: Uuid,
+    email: String,
+    /// The delivery status recorded for the skip, e.g. `skipped_suppressed`.
+    status: &'static str,
+    reason: String,
+}
+
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("{0}")]
@@ -42,56 +92,697 @@ impl ResponseError for PublishError {
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_newsletter", self.to_string())
+            }
+            PublishError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(pool, body, email_client, base_url, tracking, actor, abort),
+    fields(title = %body.title)
+)]
 #[post("/newsletters")]
 pub async fn publish_newsletter(
     pool: web::Data<PgPool>,
     body: web::Json<BodyData>,
     email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    tracking: web::Data<Tracking>,
+    actor: AuditActor,
+    abort: web::Data<DeliveryAbort>,
 ) -> Result<HttpResponse, PublishError> {
-    // get all subscribed user
-    let subscribers = get_confirmed_subscribers(&pool).await?;
+    let published = publish_issue(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &tracking,
+        &actor,
+        &body,
+        &abort,
+    )
+    .await?;
+
+    if published.deliveries.failed > 0 {
+        Ok(HttpResponse::Accepted().json(published))
+    } else {
+        Ok(HttpResponse::Ok().json(published))
+    }
+}
+
+/// Stores a new issue and delivers it to every confirmed subscriber.
+///
+/// Only fails if nothing could be sent or recorded: deliveries that fail
+/// make the issue `partial` instead.
+pub async fn publish_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracking: &Tracking,
+    actor: &AuditActor,
+    body: &BodyData,
+    abort: &DeliveryAbort,
+) -> Result<PublishedIssue, PublishError> {
+    let track_opens = body.track_opens && tracking.open_tracking_enabled;
+    let track_clicks = body.track_clicks && tracking.click_tracking_enabled;
+    let newsletter_issue_id = insert_newsletter_issue(pool, body, track_opens, track_clicks)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    record_audit_event(
+        pool,
+        actor,
+        "newsletter.published",
+        Some(&newsletter_issue_id.to_string()),
+        serde_json::json!({
+            "title": body.title,
+            "track_opens": track_opens,
+            "track_clicks": track_clicks,
+        }),
+    )
+    .await
+    .context("Failed to record the newsletter issue in the audit log")?;
+    let renderer = IssueRenderer {
+        html: &body.content.html,
+        newsletter_issue_id,
+        base_url,
+        open_tracking: track_opens.then_some(&tracking.signer),
+        click_tracking: track_clicks.then_some(&tracking.signer),
+    };
+    // stream all subscribed user
+    let subscribers = get_confirmed_subscribers(pool);
     // send mail to all subscribed users
-    //
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(&subscriber.email, &body.title, &body.content.html)
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
+    let deliveries = deliver_issue(
+        pool,
+        email_client,
+        newsletter_issue_id,
+        subscribers,
+        &body.title,
+        &renderer,
+        abort,
+    )
+    .await?;
+
+    Ok(PublishedIssue {
+        newsletter_issue_id,
+        status: if deliveries.failed > 0 {
+            "partial"
+        } else {
+            "sent"
+        },
+        deliveries,
+    })
+}
+
+struct StoredIssue {
+    title: String,
+    html_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+/// How long a delivery may stay `queued`, or an issue go without a delivery
+/// for a subscriber, before it is assumed that its publish was cut short.
+const STALE_DELIVERY_AGE_MINUTES: i64 = 60;
+
+/// Sends an issue again to the subscribers who are still confirmed and did
+/// not get it, see `get_undelivered_subscribers`. Returns how many
+/// deliveries were retried.
+///
+/// An interrupted batch may have reached the provider before it was
+/// recorded as `failed`, so its recipients can get the issue twice.
+#[tracing::instrument(
+    name = "Retry failed newsletter issue deliveries",
+    skip(pool, email_client, base_url, tracking)
+)]
+pub async fn retry_failed_deliveries(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracking: &Tracking,
+    newsletter_issue_id: Uuid,
+) -> Result<usize, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        StoredIssue,
+        r#"
+        SELECT title, html_content, track_opens, track_clicks
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the newsletter issue")?
+    .with_context(|| format!("There is no newsletter issue {}", newsletter_issue_id))?;
+    let subscribers = get_undelivered_subscribers(pool, newsletter_issue_id)
+        .await
+        .context("Failed to fetch undelivered subscribers")?;
+    let retried = subscribers.len();
+    let renderer = IssueRenderer {
+        html: &issue.html_content,
+        newsletter_issue_id,
+        base_url,
+        open_tracking: issue.track_opens.then_some(&tracking.signer),
+        click_tracking: issue.track_clicks.then_some(&tracking.signer),
+    };
+    let deliveries = deliver_issue(
+        pool,
+        email_client,
+        newsletter_issue_id,
+        stream::iter(subscribers.into_iter().map(Ok)),
+        &issue.title,
+        &renderer,
+        &DeliveryAbort::never(),
+    )
+    .await?;
+    if deliveries.failed > 0 {
+        anyhow::bail!(
+            "Failed to send newsletter issue to {} of {} subscribers",
+            deliveries.failed,
+            deliveries.sent + deliveries.failed
+        );
+    }
+    Ok(retried)
+}
+
+#[tracing::instrument(name = "Save newsletter issue details", skip(pool, body))]
+async fn insert_newsletter_issue(
+    pool: &PgPool,
+    body: &BodyData,
+    track_opens: bool,
+    track_clicks: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content,
+            track_opens, track_clicks, published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+        newsletter_issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        track_opens,
+        track_clicks,
+    )
+    .execute(pool)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Fans the issue out to `subscribers` in batches of
+/// `EmailClient::batch_size`, keeping up to
+/// `EmailClient::max_concurrent_requests` batches in flight at once.
+///
+/// The rate limit itself is enforced by the `EmailClient`. Every recipient
+/// gets a row in `issue_deliveries`, moving from `queued` to `sent` or
+/// `failed`; subscribers whose stored email is invalid or who are on the
+/// suppression list are recorded as `skipped_invalid_email` and
+/// `skipped_suppressed` respectively. A failed batch does not stop the others:
+/// failures are tallied and returned once every batch has been attempted.
+///
+/// `renderer` personalises the HTML of each recipient, e.g. to embed their
+/// open tracking pixel or rewrite links for click tracking.
+///
+/// Once `abort` fires, no further subscriber is picked up, batches waiting on
+/// the provider are recorded as `failed`, and so are the confirmed
+/// subscribers who have no delivery yet, for `queue retry-failed`.
+#[tracing::instrument(
+    name = "Delivering a newsletter issue",
+    skip_all,
+    fields(
+        %newsletter_issue_id,
+        concurrency = email_client.max_concurrent_requests(),
+        batch_size = email_client.batch_size(),
+        sent = tracing::field::Empty,
+        failed = tracing::field::Empty,
+        skipped = tracing::field::Empty,
+        elapsed_ms = tracing::field::Empty,
+        emails_per_second = tracing::field::Empty,
+    )
+)]
+async fn deliver_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    newsletter_issue_id: Uuid,
+    subscribers: impl Stream<
+        Item = Result<Result<ConfirmedSubscriber, SkippedSubscriber>, anyhow::Error>,
+    >,
+    title: &str,
+    renderer: &IssueRenderer<'_>,
+    abort: &DeliveryAbort,
+) -> Result<DeliverySummary, anyhow::Error> {
+    let start = std::time::Instant::now();
+    let sent = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+
+    let outcome = subscribers
+        .take_until(abort.aborted())
+        .try_filter_map(|subscriber| {
+            let skipped = &skipped;
+            async move {
+                match subscriber {
+                    Ok(subscriber) => Ok(Some(subscriber)),
+                    Err(skipped_subscriber) => {
+                        record_skipped_delivery(pool, newsletter_issue_id, &skipped_subscriber)
+                            .await
+                            .context("Failed to record a skipped delivery")?;
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                        Ok(None)
+                    }
+                }
             }
-            Err(error) => {
-                tracing::warn!(
-                // We record the error chain as a structured field // on the log record.
-                error.cause_chain = ?error,
-                // Using `\` to split a long string literal over // two lines, without creating a `\n` character.
-                "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid",
-                );
+        })
+        .try_chunks(email_client.batch_size())
+        .map_err(|error| error.1)
+        .try_for_each_concurrent(email_client.max_concurrent_requests(), |batch| {
+            let (sent, failed) = (&sent, &failed);
+            async move {
+                queue_deliveries(pool, newsletter_issue_id, &batch)
+                    .await
+                    .context("Failed to queue newsletter issue deliveries")?;
+
+                let html: Vec<_> = batch.iter().map(|s| renderer.render(s.id)).collect();
+                let send = async {
+                    match batch.as_slice() {
+                        [subscriber] => email_client
+                            .send_email(
+                                &subscriber.email,
+                                title,
+                                html[0].as_deref().unwrap_or(renderer.html),
+                            )
+                            .await
+                            .map(|message_id| vec![message_id]),
+                        subscribers => {
+                            let recipients: Vec<_> = subscribers
+                                .iter()
+                                .zip(&html)
+                                .map(|(s, html)| BatchRecipient {
+                                    email: &s.email,
+                                    html: html.as_deref(),
+                                })
+                                .collect();
+                            email_client
+                                .send_email_batch(&recipients, title, renderer.html)
+                                .await
+                        }
+                    }
+                };
+                let outcome = tokio::select! {
+                    outcome = send => Some(outcome),
+                    () = abort.aborted() => None,
+                };
+                match outcome {
+                    Some(Ok(message_ids)) => {
+                        mark_deliveries_sent(pool, newsletter_issue_id, &batch, message_ids)
+                            .await
+                            .context("Failed to record sent deliveries")?;
+                        sent.fetch_add(batch.len(), Ordering::Relaxed);
+                    }
+                    Some(Err(error)) => {
+                        tracing::error!(
+                            error.cause_chain = ?error,
+                            recipients = batch.len(),
+                            "Failed to send newsletter issue to a batch of subscribers",
+                        );
+                        mark_deliveries_failed(
+                            pool,
+                            newsletter_issue_id,
+                            &batch,
+                            &error.to_string(),
+                        )
+                        .await
+                        .context("Failed to record failed deliveries")?;
+                        failed.fetch_add(batch.len(), Ordering::Relaxed);
+                    }
+                    None => {
+                        tracing::warn!(
+                            recipients = batch.len(),
+                            "Stopped sending newsletter issue to a batch of subscribers",
+                        );
+                        mark_deliveries_failed(
+                            pool,
+                            newsletter_issue_id,
+                            &batch,
+                            INTERRUPTED_REASON,
+                        )
+                        .await
+                        .context("Failed to record interrupted deliveries")?;
+                        failed.fetch_add(batch.len(), Ordering::Relaxed);
+                    }
+                }
+                Ok(())
             }
+        })
+        .await;
+    let outcome = match outcome {
+        Ok(()) if abort.is_aborted() => record_unreached_deliveries(pool, newsletter_issue_id)
+            .await
+            .map(|unreached| {
+                failed.fetch_add(unreached as usize, Ordering::Relaxed);
+            })
+            .context("Failed to record unreached deliveries"),
+        outcome => outcome,
+    };
+
+    let elapsed = start.elapsed();
+    let (sent, failed, skipped) = (sent.into_inner(), failed.into_inner(), skipped.into_inner());
+    let span = tracing::Span::current();
+    span.record("sent", &sent);
+    span.record("failed", &failed);
+    span.record("skipped", &skipped);
+    span.record("elapsed_ms", &(elapsed.as_millis() as u64));
+    span.record(
+        "emails_per_second",
+        &(sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON)),
+    );
+
+    outcome?;
+    Ok(DeliverySummary {
+        sent,
+        failed,
+        skipped,
+    })
+}
+
+#[tracing::instrument(name = "Queue newsletter issue deliveries", skip(pool, batch))]
+async fn queue_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    batch: &[ConfirmedSubscriber],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|s| s.id).collect();
+    let emails: Vec<String> = batch.iter().map(|s| s.email.as_ref().to_owned()).collect();
+    let request_id = RequestId::current().map(|request_id| request_id.to_string());
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_id, subscriber_email, status, request_id,
+            created_at, updated_at
+        )
+        SELECT $1, subscriber_id, subscriber_email, 'queued', $4, now(), now()
+        FROM UNNEST($2::uuid[], $3::text[]) AS t(subscriber_id, subscriber_email)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        &ids,
+        &emails,
+        request_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Mark newsletter issue deliveries as sent", skip_all)]
+async fn mark_deliveries_sent(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    batch: &[ConfirmedSubscriber],
+    message_ids: Vec<Option<String>>,
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|s| s.id).collect();
+    // Arrays cannot carry NULLs through sqlx, so a missing id travels as ''.
+    let message_ids: Vec<String> = message_ids
+        .into_iter()
+        .map(Option::unwrap_or_default)
+        .collect();
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = 'sent', provider_message_id = NULLIF(t.message_id, ''), updated_at = now()
+        FROM UNNEST($2::uuid[], $3::text[]) AS t(subscriber_id, message_id)
+        WHERE issue_deliveries.newsletter_issue_id = $1
+            AND issue_deliveries.subscriber_id = t.subscriber_id
+        "#,
+        newsletter_issue_id,
+        &ids,
+        &message_ids,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Why a delivery that shutdown stopped is `failed`.
+const INTERRUPTED_REASON: &str = "Interrupted by shutdown";
+
+#[tracing::instrument(name = "Mark newsletter issue deliveries as failed", skip_all)]
+async fn mark_deliveries_failed(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    batch: &[ConfirmedSubscriber],
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|s| s.id).collect();
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = 'failed', failure_reason = $3, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_id = ANY($2)
+        "#,
+        newsletter_issue_id,
+        &ids,
+        reason,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a `failed` delivery for every subscriber who was confirmed when
+/// the issue was published and has no delivery yet, returning how many.
+#[tracing::instrument(name = "Record unreached newsletter issue deliveries", skip(pool))]
+async fn record_unreached_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_id, subscriber_email, status,
+            failure_reason, created_at, updated_at
+        )
+        SELECT i.newsletter_issue_id, s.id, s.email, 'failed', $2, now(), now()
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.newsletter_issue_id = $1
+            AND s.status = 'confirmed'
+            AND COALESCE(
+                (SELECT MAX(changed_at) FROM subscription_status_changes
+                    WHERE subscriber_id = s.id),
+                s.subscribed_at
+            ) <= i.published_at
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        INTERRUPTED_REASON,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Records a skipped delivery, replacing one that is still `queued` or
+/// `failed`, e.g. when a retry finds the address suppressed since.
+#[tracing::instrument(name = "Record a skipped newsletter issue delivery", skip(pool))]
+async fn record_skipped_delivery(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber: &SkippedSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_id, subscriber_email, status,
+            failure_reason, created_at, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), now())
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE
+        SET status = EXCLUDED.status, failure_reason = EXCLUDED.failure_reason,
+            updated_at = now()
+        WHERE issue_deliveries.status IN ('queued', 'failed')
+        "#,
+        newsletter_issue_id,
+        subscriber.id,
+        subscriber.email,
+        subscriber.status,
+        subscriber.reason,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Number of rows fetched per round-trip when walking confirmed subscribers.
+const CONFIRMED_SUBSCRIBERS_PAGE_SIZE: i64 = 1000;
+
+/// Streams confirmed subscribers page by page, using the primary key as a
+/// keyset cursor, so memory stays flat however large the list grows.
+///
+/// The outer `Result` is a database failure, which should abort the walk,
+/// while the inner one flags a single subscriber who must be skipped.
+pub fn get_confirmed_subscribers(
+    pool: &PgPool,
+) -> impl Stream<Item = Result<Result<ConfirmedSubscriber, SkippedSubscriber>, anyhow::Error>> + '_
+{
+    stream::try_unfold(Some(Uuid::nil()), move |cursor| async move {
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+        let page = get_confirmed_subscribers_page(pool, cursor).await?;
+        let next_cursor = match page.last() {
+            Some(last) if page.len() as i64 == CONFIRMED_SUBSCRIBERS_PAGE_SIZE => Some(last.id),
+            _ => None,
+        };
+        let subscribers = page.into_iter().map(|r| Ok(r.into_subscriber()));
+        Ok::<_, anyhow::Error>(Some((stream::iter(subscribers), next_cursor)))
+    })
+    .try_flatten()
+}
+
+struct ConfirmedSubscriberRow {
+    id: Uuid,
+    email: String,
+    suppression_reason: Option<String>,
+}
+
+impl ConfirmedSubscriberRow {
+    fn into_subscriber(self) -> Result<ConfirmedSubscriber, SkippedSubscriber> {
+        if let Some(suppression_reason) = self.suppression_reason {
+            return Err(SkippedSubscriber {
+                id: self.id,
+                email: self.email,
+                status: "skipped_suppressed",
+                reason: format!("The address is suppressed ({})", suppression_reason),
+            });
+        }
+        match SubscriberEmail::parse(self.email.clone()) {
+            Ok(email) => Ok(ConfirmedSubscriber { id: self.id, email }),
+            Err(reason) => Err(SkippedSubscriber {
+                id: self.id,
+                email: self.email,
+                status: "skipped_invalid_email",
+                reason,
+            }),
         }
     }
+}
+
+#[tracing::instrument(name = "Get a page of confirmed subscribers", skip(pool))]
+async fn get_confirmed_subscribers_page(
+    pool: &PgPool,
+    after: Uuid,
+) -> Result<Vec<ConfirmedSubscriberRow>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        ConfirmedSubscriberRow,
+        r#"
+        SELECT s.id, s.email, sp.reason AS "suppression_reason?"
+        FROM subscriptions s
+        LEFT JOIN suppressions sp ON sp.email_hash = s.email_hash
+        WHERE s.status = 'confirmed' AND s.id > $1
+        ORDER BY s.id
+        LIMIT $2
+        "#,
+        after,
+        CONFIRMED_SUBSCRIBERS_PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
 
-    Ok(HttpResponse::Ok().finish())
+fn stale_delivery_cutoff() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::minutes(STALE_DELIVERY_AGE_MINUTES)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-pub async fn get_confirmed_subscribers(
+/// Still confirmed subscribers who did not get the issue: their delivery
+/// `failed`, has been `queued` for longer than `STALE_DELIVERY_AGE_MINUTES`,
+/// or is missing although they were confirmed when the issue was published,
+/// that long ago.
+#[tracing::instrument(name = "Get subscribers with undelivered issues", skip(pool))]
+async fn get_undelivered_subscribers(
     pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirowsrmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status='confirmed'"#,)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|r| match SubscriberEmail::parse(r.email) {
-                Ok(email) => Ok(ConfirmedSubscriber { email }),
-                Err(error) => Err(anyhow::anyhow!(error)),
-            })
-            .collect();
-    Ok(confirowsrmed_subscribers)
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<Result<ConfirmedSubscriber, SkippedSubscriber>>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        ConfirmedSubscriberRow,
+        r#"
+        SELECT s.id, s.email, sp.reason AS "suppression_reason?"
+        FROM newsletter_issues i
+        JOIN subscriptions s ON s.status = 'confirmed'
+        LEFT JOIN issue_deliveries d
+            ON d.newsletter_issue_id = i.newsletter_issue_id AND d.subscriber_id = s.id
+        LEFT JOIN suppressions sp ON sp.email_hash = s.email_hash
+        WHERE i.newsletter_issue_id = $1 AND (
+            d.status = 'failed'
+            OR (d.status = 'queued' AND d.updated_at < $2)
+            OR (
+                d.subscriber_id IS NULL
+                AND i.published_at < $2
+                AND COALESCE(
+                    (SELECT MAX(changed_at) FROM subscription_status_changes
+                        WHERE subscriber_id = s.id),
+                    s.subscribed_at
+                ) <= i.published_at
+            )
+        )
+        ORDER BY s.id
+        "#,
+        newsletter_issue_id,
+        stale_delivery_cutoff(),
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.into_subscriber()).collect())
+}
+
+/// Issues with subscribers for `retry_failed_deliveries`, oldest first.
+#[tracing::instrument(name = "Get issues with undelivered subscribers", skip(pool))]
+pub async fn get_issues_with_undelivered_subscribers(
+    pool: &PgPool,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT i.newsletter_issue_id
+        FROM newsletter_issues i
+        WHERE EXISTS (
+            SELECT 1 FROM issue_deliveries d
+            WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND (d.status = 'failed' OR (d.status = 'queued' AND d.updated_at < $1))
+        ) OR (
+            i.published_at < $1
+            AND EXISTS (
+                SELECT 1 FROM subscriptions s
+                WHERE s.status = 'confirmed'
+                    AND COALESCE(
+                        (SELECT MAX(changed_at) FROM subscription_status_changes
+                            WHERE subscriber_id = s.id),
+                        s.subscribed_at
+                    ) <= i.published_at
+                    AND NOT EXISTS (
+                        SELECT 1 FROM issue_deliveries d
+                        WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                            AND d.subscriber_id = s.id
+                    )
+            )
+        )
+        ORDER BY i.published_at
+        "#,
+        stale_delivery_cutoff(),
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(issues.into_iter().map(|r| r.newsletter_issue_id).collect())
 }
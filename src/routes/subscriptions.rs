@@ -1,7 +1,10 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
 use crate::email_client::EmailClient;
 use crate::helper::error_chain_fmt;
+use crate::problem::{FieldError, Problem};
 use crate::startup::ApplicationBaseUrl;
+use crate::subscription_status::record_initial_status;
+use crate::suppression::{email_hash, suppression_reason};
 use actix_web::{post, web, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
@@ -18,12 +21,19 @@ pub struct FormData {
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
+    /// Validates every field, so all the invalid ones are reported at once.
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { name, email })
+        let name = SubscriberName::parse(value.name);
+        let email = SubscriberEmail::parse(value.email);
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => Err([("name", name.err()), ("email", email.err())]
+                .into_iter()
+                .filter_map(|(field, error)| error.map(|message| FieldError { field, message }))
+                .collect()),
+        }
     }
 }
 
@@ -60,6 +70,9 @@ pub async fn subscribe(
     let sid = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
+    record_initial_status(&mut transaction, sid, "pending_confirmation")
+        .await
+        .context("Failed to record the status of a new subscriber.")?;
 
     let subscription_token = generate_subscription_token();
 
@@ -73,7 +86,9 @@ pub async fn subscribe(
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
     send_confirmation_email(
+        &pool,
         &email_client,
+        sid,
         new_subscriber,
         &base_url.0,
         &subscription_token,
@@ -95,11 +110,12 @@ pub async fn insert_subscriber(
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, email_hash, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
         "#,
         subscriber_id,
         form.email.as_ref(),
+        email_hash(form.email.as_ref()),
         form.name.as_ref(),
         Utc::now()
     )
@@ -110,14 +126,38 @@ pub async fn insert_subscriber(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber)
+    skip(pool, email_client, new_subscriber)
 )]
 pub async fn send_confirmation_email(
+    pool: &PgPool,
     email_client: &EmailClient,
+    subscriber_id: Uuid,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
+    let suppression_reason = suppression_reason(pool, new_subscriber.email.as_ref())
+        .await
+        .context("Failed to check the suppression list")?;
+    if let Some(reason) = suppression_reason {
+        tracing::info!(
+            delivery.kind = "confirmation",
+            delivery.status = "skipped_suppressed",
+            delivery.email_hash = %email_hash(new_subscriber.email.as_ref()),
+            delivery.reason = %reason,
+            "Skipping the confirmation email. The address is suppressed",
+        );
+        record_skipped_confirmation_email(
+            pool,
+            subscriber_id,
+            "skipped_suppressed",
+            &format!("The address is suppressed ({})", reason),
+        )
+        .await
+        .context("Failed to record a skipped confirmation email")?;
+        return Ok(());
+    }
+
     let confirmation_link: String = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -130,12 +170,35 @@ pub async fn send_confirmation_email(
 
     email_client
         .send_email(&new_subscriber.email, "Welcome", &html_body)
-        .await
-        .unwrap();
+        .await?;
 
     Ok(())
 }
 
+#[tracing::instrument(name = "Record a skipped confirmation email", skip(pool))]
+async fn record_skipped_confirmation_email(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    status: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO skipped_confirmation_emails (
+            skipped_confirmation_email_id, subscriber_id, status, reason, created_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        status,
+        reason,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 #[tracing::instrument(
     name = "Store scubscription token in db",
     skip(subscription_token, pool)
@@ -182,8 +245,8 @@ impl std::error::Error for StoreTokenError {
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("The subscription details are invalid.")]
+    ValidationError(Vec<FieldError>),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -201,4 +264,15 @@ impl ResponseError for SubscribeError {
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError(errors) => {
+                Problem::new(self.status_code(), "invalid_subscription", self.to_string())
+                    .with_errors(errors)
+            }
+            SubscribeError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
 }
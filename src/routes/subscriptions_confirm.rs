@@ -5,6 +5,8 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::subscription_status::change_status;
 
 #[derive(thiserror::Error)]
 enum ConfirmationError {
@@ -27,6 +29,18 @@ impl ResponseError for ConfirmationError {
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmationError::InvalidToken => Problem::new(
+                self.status_code(),
+                "invalid_confirmation_token",
+                self.to_string(),
+            ),
+            ConfirmationError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -54,13 +68,7 @@ pub async fn confirm(
 
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
 pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
-    )
-    .execute(pool)
-    .await?;
-
+    change_status(pool, subscriber_id, "confirmed").await?;
     Ok(())
 }
 
@@ -0,0 +1,184 @@
+use crate::data_export::{
+    create_export_token, export_subscriber_data, find_export_link, has_pending_export_link,
+    SubscriberExport, EXPORT_LINK_VALIDITY_HOURS,
+};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::get_subscriber_id_from_token;
+use crate::startup::ApplicationBaseUrl;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, post, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+#[derive(thiserror::Error)]
+pub enum DataExportError {
+    #[error("There is no subscriber associated with the provided token.")]
+    InvalidSubscriptionToken,
+    #[error("An export link was sent recently. Use it, or request a new one once it expires.")]
+    ExportLinkPending,
+    #[error("The export link is invalid.")]
+    InvalidExportToken,
+    #[error("The export link has expired. Request a new one.")]
+    ExpiredExportToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DataExportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataExportError::InvalidSubscriptionToken => StatusCode::UNAUTHORIZED,
+            DataExportError::ExportLinkPending => StatusCode::CONFLICT,
+            DataExportError::InvalidExportToken => StatusCode::UNAUTHORIZED,
+            DataExportError::ExpiredExportToken => StatusCode::GONE,
+            DataExportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            DataExportError::InvalidSubscriptionToken => Problem::new(
+                self.status_code(),
+                "invalid_subscription_token",
+                self.to_string(),
+            ),
+            DataExportError::ExportLinkPending => {
+                Problem::new(self.status_code(), "export_link_pending", self.to_string())
+            }
+            DataExportError::InvalidExportToken => {
+                Problem::new(self.status_code(), "invalid_export_token", self.to_string())
+            }
+            DataExportError::ExpiredExportToken => {
+                Problem::new(self.status_code(), "export_link_expired", self.to_string())
+            }
+            DataExportError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportRequestParameters {
+    subscription_token: String,
+}
+
+/// Emails the subscriber a link to download everything held about them.
+/// The data itself is only handed out through that link, so that it goes
+/// to the address it belongs to. Only one link is valid at a time, so the
+/// endpoint cannot be used to flood the subscriber's inbox.
+#[tracing::instrument(
+    name = "Request a subscriber data export",
+    skip(parameters, pool, email_client, base_url)
+)]
+#[post("/subscriptions/me/export")]
+pub async fn request_data_export(
+    parameters: web::Form<ExportRequestParameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, DataExportError> {
+    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(DataExportError::InvalidSubscriptionToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Locking the subscriber serialises concurrent requests, so that only
+    // one of them finds no pending link.
+    let email = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to retrieve the subscriber's email address.")?
+    .email;
+    if has_pending_export_link(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to look up pending export links.")?
+    {
+        return Err(DataExportError::ExportLinkPending);
+    }
+    let email = SubscriberEmail::parse(email)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("The subscriber's stored email address is invalid.")?;
+
+    let export_token = create_export_token(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to store the export link.")?;
+    let download_link = format!(
+        "{}/subscriptions/me/export/download?export_token={}",
+        base_url.0, export_token
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to download the data we hold about you.<br />\
+                The link expires in {} hours.",
+        download_link, EXPORT_LINK_VALIDITY_HOURS
+    );
+    email_client
+        .send_email(&email, "Your data export", &html_body)
+        .await
+        .context("Failed to send the export link.")?;
+    // Only once the email is out, so a failed send does not hold back the
+    // next request.
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store the export link.")?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportDownloadParameters {
+    export_token: String,
+}
+
+/// Serves the data export an emailed link points to, as a JSON file.
+#[tracing::instrument(name = "Download a subscriber data export", skip(parameters, pool))]
+#[get("/subscriptions/me/export/download")]
+pub async fn download_data_export(
+    parameters: web::Query<ExportDownloadParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataExportError> {
+    let link = find_export_link(pool.get_ref(), &parameters.export_token)
+        .await
+        .context("Failed to look up the export link.")?
+        .ok_or(DataExportError::InvalidExportToken)?;
+    if link.expires_at <= Utc::now() {
+        return Err(DataExportError::ExpiredExportToken);
+    }
+    let export = export_subscriber_data(&pool, link.subscriber_id)
+        .await
+        .context("Failed to export the subscriber's data.")?
+        // The link goes with the subscriber.
+        .ok_or(DataExportError::InvalidExportToken)?;
+
+    Ok(export_attachment(&export))
+}
+
+/// The export as a JSON file download.
+pub fn export_attachment(export: &SubscriberExport) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscriber-{}.json",
+                export.subscriber.id
+            ))],
+        })
+        .json(export)
+}
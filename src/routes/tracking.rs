@@ -0,0 +1,80 @@
+use crate::tracking::{
+    ClickEvent, ClickToken, OpenEvent, OpenToken, Tracking, TrackingEvent, TRANSPARENT_GIF,
+};
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+/// Serves the open tracking pixel.
+///
+/// The token is verified by its signature alone and the open is handed to
+/// the in-memory buffer, so the request never waits on the database. The
+/// pixel is returned whatever the token, so mail clients never show a
+/// broken image.
+#[tracing::instrument(name = "Track an email open", skip(request, tracking, token))]
+#[get("/t/o/{token}.gif")]
+pub async fn track_open(
+    request: HttpRequest,
+    token: web::Path<String>,
+    tracking: web::Data<Tracking>,
+) -> HttpResponse {
+    match OpenToken::verify(&token, &tracking.signer) {
+        Some(token) => tracking.events.record(TrackingEvent::Open(OpenEvent {
+            newsletter_issue_id: token.newsletter_issue_id,
+            subscriber_id: token.subscriber_id,
+            opened_at: chrono::Utc::now(),
+            user_agent: user_agent(&request),
+        })),
+        None => tracing::warn!("Ignoring an open with an invalid tracking token"),
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(no_store())
+        .body(TRANSPARENT_GIF)
+}
+
+/// Records a click and redirects to the link's original destination.
+///
+/// Only destinations signed into the token are honoured: anything else is a
+/// 404, so the endpoint cannot be used as an open redirect.
+#[tracing::instrument(name = "Track a link click", skip(request, tracking, token))]
+#[get("/t/c/{token}")]
+pub async fn track_click(
+    request: HttpRequest,
+    token: web::Path<String>,
+    tracking: web::Data<Tracking>,
+) -> HttpResponse {
+    let token = match ClickToken::verify(&token, &tracking.signer) {
+        Some(token) => token,
+        None => {
+            tracing::warn!("Rejecting a click with an invalid tracking token");
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    let location = token.url.clone();
+    tracking.events.record(TrackingEvent::Click(ClickEvent {
+        newsletter_issue_id: token.newsletter_issue_id,
+        subscriber_id: token.subscriber_id,
+        url: token.url,
+        clicked_at: chrono::Utc::now(),
+        user_agent: user_agent(&request),
+    }));
+
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .insert_header(no_store())
+        .finish()
+}
+
+fn user_agent(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Every hit must reach us, so neither browsers nor proxies may cache it.
+fn no_store() -> CacheControl {
+    CacheControl(vec![CacheDirective::NoStore, CacheDirective::Private])
+}
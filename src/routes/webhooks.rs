@@ -0,0 +1,322 @@
+use crate::authentication::has_bearer_token;
+use crate::helper::error_chain_fmt;
+use crate::problem::Problem;
+use crate::startup::WebhookSecret;
+use crate::subscription_status::change_status_by_email;
+use crate::suppression::{add_suppression, email_hash, SuppressionReason};
+use actix_web::{post, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// A single event as posted by Brevo's transactional webhook.
+#[derive(serde::Deserialize, Debug)]
+pub struct EmailEvent {
+    event: EmailEventKind,
+    email: String,
+    #[serde(rename = "message-id")]
+    message_id: Option<String>,
+    ts_event: Option<i64>,
+    reason: Option<String>,
+    link: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailEventKind {
+    Delivered,
+    HardBounce,
+    SoftBounce,
+    Spam,
+    Unsubscribed,
+    Opened,
+    Click,
+    #[serde(other)]
+    Unsupported,
+}
+
+impl EmailEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEventKind::Delivered => "delivered",
+            EmailEventKind::HardBounce => "hard_bounce",
+            EmailEventKind::SoftBounce => "soft_bounce",
+            EmailEventKind::Spam => "spam",
+            EmailEventKind::Unsubscribed => "unsubscribed",
+            EmailEventKind::Opened => "opened",
+            EmailEventKind::Click => "click",
+            EmailEventKind::Unsupported => "unsupported",
+        }
+    }
+
+    /// The delivery status an event moves the matching issue delivery to, if
+    /// any. Opens and clicks are recorded as events only.
+    fn delivery_status(&self) -> Option<&'static str> {
+        match self {
+            EmailEventKind::Delivered => Some("delivered"),
+            EmailEventKind::HardBounce => Some("bounced"),
+            EmailEventKind::SoftBounce => Some("soft_bounced"),
+            EmailEventKind::Spam => Some("complained"),
+            _ => None,
+        }
+    }
+
+    /// The delivery statuses `delivery_status` may replace. Events can
+    /// arrive out of order, so a delivery only moves forward, and never
+    /// out of `bounced` or `complained`.
+    fn replaced_delivery_statuses(&self) -> &'static [&'static str] {
+        match self {
+            EmailEventKind::Delivered => &["sent", "soft_bounced"],
+            EmailEventKind::SoftBounce => &["sent"],
+            EmailEventKind::HardBounce | EmailEventKind::Spam => {
+                &["sent", "soft_bounced", "delivered"]
+            }
+            _ => &[],
+        }
+    }
+
+    /// The subscription status an event moves the subscriber to, if any.
+    ///
+    /// `bounced` and `complained` are suppressed states: only `confirmed`
+    /// subscribers receive newsletter issues.
+    fn subscription_status(&self) -> Option<&'static str> {
+        match self {
+            EmailEventKind::HardBounce => Some("bounced"),
+            EmailEventKind::Spam => Some("complained"),
+            EmailEventKind::Unsubscribed => Some("unsubscribed"),
+            _ => None,
+        }
+    }
+
+    /// Hard bounces and complaints put the address on the suppression list,
+    /// so it stays unmailable even if it subscribes again.
+    fn suppression_reason(&self) -> Option<SuppressionReason> {
+        match self {
+            EmailEventKind::HardBounce => Some(SuppressionReason::Bounce),
+            EmailEventKind::Spam => Some(SuppressionReason::Complaint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook request could not be authenticated.")]
+    Unauthorized,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::Unauthorized => StatusCode::UNAUTHORIZED,
+            WebhookError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::Unauthorized => {
+                Problem::new(self.status_code(), "unauthorized", self.to_string())
+            }
+            WebhookError::ValidationError(_) => {
+                Problem::new(self.status_code(), "invalid_event", self.to_string())
+            }
+            WebhookError::UnexpectedError(_) => Problem::unexpected(),
+        }
+        .into()
+    }
+}
+
+#[tracing::instrument(
+    name = "Receive an email provider event",
+    skip(request, body, pool, secret),
+    fields(event = tracing::field::Empty, provider_message_id = tracing::field::Empty)
+)]
+#[post("/webhooks/email-events")]
+pub async fn receive_email_events(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    secret: web::Data<WebhookSecret>,
+) -> Result<HttpResponse, WebhookError> {
+    verify_shared_secret(&request, &secret)?;
+
+    let event: EmailEvent = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::ValidationError(format!("Invalid event payload: {}", e)))?;
+    tracing::Span::current()
+        .record("event", &event.event.as_str())
+        .record("provider_message_id", &event.message_id.as_deref());
+
+    if event.event == EmailEventKind::Unsupported {
+        // Acknowledge so the provider does not keep retrying events we do
+        // not track.
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    apply_email_event(&mut transaction, &event)
+        .await
+        .context("Failed to apply the email event")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an email event.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn verify_shared_secret(request: &HttpRequest, secret: &WebhookSecret) -> Result<(), WebhookError> {
+    if has_bearer_token(request, &secret.0) {
+        Ok(())
+    } else {
+        Err(WebhookError::Unauthorized)
+    }
+}
+
+async fn apply_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &EmailEvent,
+) -> Result<(), sqlx::Error> {
+    let newsletter_issue_id = match (&event.message_id, event.event.delivery_status()) {
+        (Some(message_id), Some(status)) => {
+            let replaced = event.event.replaced_delivery_statuses();
+            match update_delivery_status(transaction, message_id, status, replaced).await? {
+                Some(newsletter_issue_id) => Some(newsletter_issue_id),
+                // The delivery is already past this event.
+                None => find_delivery_issue(transaction, message_id).await?,
+            }
+        }
+        (Some(message_id), None) => find_delivery_issue(transaction, message_id).await?,
+        (None, _) => None,
+    };
+
+    insert_email_event(transaction, event, newsletter_issue_id).await?;
+
+    if let Some(status) = event.event.subscription_status() {
+        change_status_by_email(&mut *transaction, &event.email, status).await?;
+    }
+    if let Some(reason) = event.event.suppression_reason() {
+        add_suppression(&mut *transaction, &event.email, reason, "webhook").await?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Update delivery status from an email event", skip(transaction))]
+async fn update_delivery_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    provider_message_id: &str,
+    status: &str,
+    replaced: &[&str],
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let replaced: Vec<String> = replaced.iter().map(|s| s.to_string()).collect();
+    let row = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = $2, updated_at = now()
+        WHERE provider_message_id = $1 AND status = ANY($3)
+        RETURNING newsletter_issue_id
+        "#,
+        provider_message_id,
+        status,
+        &replaced,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.newsletter_issue_id))
+}
+
+#[tracing::instrument(name = "Find the issue of a delivery", skip(transaction))]
+async fn find_delivery_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    provider_message_id: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT newsletter_issue_id FROM issue_deliveries WHERE provider_message_id = $1"#,
+        provider_message_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.newsletter_issue_id))
+}
+
+#[tracing::instrument(name = "Store an email event", skip(transaction, event))]
+async fn insert_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &EmailEvent,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    let occurred_at: DateTime<Utc> = event
+        .ts_event
+        .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+        .unwrap_or_else(Utc::now);
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            email_event_id, event, email, email_hash, provider_message_id,
+            newsletter_issue_id, reason, link, occurred_at, received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())
+        "#,
+        Uuid::new_v4(),
+        event.event.as_str(),
+        event.email,
+        email_hash(&event.email),
+        event.message_id,
+        newsletter_issue_id,
+        event.reason,
+        event.link,
+        occurred_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailEvent, EmailEventKind};
+
+    #[test]
+    fn brevo_payloads_are_parsed() {
+        let event: EmailEvent = serde_json::from_value(serde_json::json!({
+            "event": "hard_bounce",
+            "email": "reader@example.com",
+            "id": 26224,
+            "date": "2022-10-19 10:00:00",
+            "message-id": "<abc@brevo>",
+            "ts_event": 1666173600,
+            "reason": "mailbox does not exist",
+        }))
+        .unwrap();
+
+        assert_eq!(event.event, EmailEventKind::HardBounce);
+        assert_eq!(event.message_id.as_deref(), Some("<abc@brevo>"));
+    }
+
+    #[test]
+    fn unknown_event_kinds_are_unsupported() {
+        let event: EmailEvent = serde_json::from_value(serde_json::json!({
+            "event": "proxy_open",
+            "email": "reader@example.com",
+        }))
+        .unwrap();
+
+        assert_eq!(event.event, EmailEventKind::Unsupported);
+    }
+}
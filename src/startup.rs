@@ -1,37 +1,74 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::configuration::{
+    DatabaseSettings, EmailClientSettings, HealthSettings, Settings, TrackingSettings,
+};
 use crate::email_client::EmailClient;
+use crate::migrations::run_migrations;
+use crate::monitoring::{init_metrics, HttpRequestTimer};
+use crate::request_id::{echo_request_id, RequestId, RequestIdRootSpanBuilder};
 use crate::routes::*;
-use actix_web::dev::Server;
+use crate::tracking::{Tracking, TrackingEventBuffer, TrackingEventFlusher, TrackingSigner};
+use actix_web::dev::{Server, Service};
 use actix_web::web::Data;
-use actix_web::{web, App, HttpServer};
+use actix_web::{web, App, HttpMessage, HttpServer};
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
-use std::net::TcpListener;
+use std::future::Future;
+use std::net::{IpAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    tracking_flusher: TrackingEventFlusher,
+    in_flight: InFlightRequests,
+    abort_deliveries: watch::Sender<bool>,
+    shutdown_timeout: Duration,
 }
 
+/// Tracking events written to Postgres in a single flush.
+const TRACKING_EVENTS_BATCH_SIZE: usize = 500;
+
+/// How long before the shutdown deadline newsletter deliveries still
+/// running are told to stop, at most half of the shutdown timeout.
+const DELIVERY_ABORT_MARGIN: Duration = Duration::from_secs(2);
+
 #[derive(Debug)]
 pub struct ApplicationBaseUrl(pub String);
 
+#[derive(Debug)]
+pub struct WebhookSecret(pub Secret<String>);
+
+#[derive(Debug)]
+pub struct AdminApiToken(pub Secret<String>);
+
+#[derive(Debug)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+        init_metrics();
+        if configuration.application.run_migrations_on_startup {
+            run_migrations(&configuration.database)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.auth_token,
-            timeout,
+        let email_client = get_email_client(&configuration.email_client);
+        // The flusher gets its own pool: connections opened by the HTTP
+        // workers are tied to their runtimes, which are gone by the time
+        // the final flush runs at shutdown.
+        let (tracking, tracking_flusher) = get_tracking(
+            &configuration.tracking,
+            get_connection_pool(&configuration.database),
         );
 
         let port =
@@ -42,21 +79,231 @@ impl Application {
         tracing::info!("Address: {:?}", address);
         let listener = TcpListener::bind(address).expect("Failed to bind random port");
         let port = listener.local_addr().unwrap().port();
+
+        let shutdown_timeout = configuration.application.shutdown_timeout();
+        let (metrics_port, metrics_server) = match configuration.application.metrics_port {
+            Some(metrics_port) => {
+                let address = format!("{}:{}", configuration.application.host, metrics_port);
+                tracing::info!("Metrics address: {:?}", address);
+                let listener = TcpListener::bind(address)?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                let server = run_metrics(listener, connection_pool.clone(), shutdown_timeout)?;
+                (Some(metrics_port), Some(server))
+            }
+            None => (None, None),
+        };
+
+        let in_flight = InFlightRequests::default();
+        let (abort_deliveries, delivery_abort) = watch::channel(false);
         let server = run(
             listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
+            AppState {
+                db_pool: connection_pool,
+                email_client,
+                base_url: configuration.application.base_url,
+                webhook_secret: configuration.webhooks.shared_secret,
+                admin_api_token: configuration.admin.api_token,
+                trusted_proxies: configuration.application.trusted_proxies,
+                tracking,
+                health: configuration.health,
+                in_flight: in_flight.clone(),
+                delivery_abort: DeliveryAbort(delivery_abort),
+            },
+            metrics_server.is_none(),
+            shutdown_timeout,
         )?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+            tracking_flusher,
+            in_flight,
+            abort_deliveries,
+            shutdown_timeout,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The port `/metrics` is served on, when it is not the public one.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// Serves requests until SIGTERM or Ctrl-C, then shuts down gracefully.
     pub async fn run(self) -> Result<(), std::io::Error> {
-        self.server.await
+        self.run_until_stopped(shutdown_signal()).await
+    }
+
+    /// Serves requests until `shutdown` completes. The servers then stop
+    /// accepting connections, in-flight requests, including newsletter
+    /// deliveries, are left to finish and buffered tracking events are
+    /// written, all within `application.shutdown_timeout_seconds`.
+    ///
+    /// Shortly before the deadline, deliveries still running are told to
+    /// stop: they record what they did not send as `failed`, for
+    /// `queue retry-failed`. Requests still running at the deadline are
+    /// dropped.
+    pub async fn run_until_stopped(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), std::io::Error> {
+        let handles: Vec<_> = std::iter::once(&self.server)
+            .chain(self.metrics_server.as_ref())
+            .map(Server::handle)
+            .collect();
+        let servers = async move {
+            match self.metrics_server {
+                Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+                None => self.server.await,
+            }
+        };
+        tokio::pin!(servers);
+
+        let result = tokio::select! {
+            // Only happens if a server fails.
+            result = &mut servers => result,
+            _ = shutdown => {
+                tracing::info!("Shutting down");
+                let deadline = Instant::now() + self.shutdown_timeout;
+                let abort_deliveries_at =
+                    deadline - DELIVERY_ABORT_MARGIN.min(self.shutdown_timeout / 2);
+                // Requests are drained before the servers are stopped:
+                // actix-server 2.1 drops the connections of a worker whose
+                // accept loop exits before it is told to stop gracefully.
+                let in_flight = &self.in_flight;
+                let abort_deliveries = &self.abort_deliveries;
+                let stop = async {
+                    for handle in &handles {
+                        handle.pause().await;
+                    }
+                    if tokio::time::timeout_at(abort_deliveries_at, in_flight.drained())
+                        .await
+                        .is_err()
+                    {
+                        tracing::warn!("Stopping newsletter deliveries close to the shutdown deadline");
+                        let _ = abort_deliveries.send(true);
+                        // Past the deadline, so are the servers, which is
+                        // logged below.
+                        let _ = tokio::time::timeout_at(deadline, in_flight.drained()).await;
+                    }
+                    for handle in &handles {
+                        // The command is sent right away; the servers
+                        // themselves complete once their workers have stopped.
+                        drop(handle.stop(true));
+                    }
+                };
+                // The servers process the commands above as they are polled.
+                let ((), drained) =
+                    tokio::join!(stop, tokio::time::timeout_at(deadline, &mut servers));
+                let flushed =
+                    tokio::time::timeout_at(deadline, self.tracking_flusher.shutdown()).await;
+                if flushed.is_err() {
+                    tracing::warn!("Gave up flushing tracking events at the shutdown deadline");
+                }
+                return drained.unwrap_or_else(|_| {
+                    tracing::warn!("Gave up on in-flight requests at the shutdown deadline");
+                    Ok(())
+                });
+            }
+        };
+        self.tracking_flusher.shutdown().await;
+        result
+    }
+}
+
+/// Tells newsletter deliveries that the shutdown deadline is close, so they
+/// stop and record what they did not send before they are dropped.
+#[derive(Clone)]
+pub struct DeliveryAbort(watch::Receiver<bool>);
+
+impl DeliveryAbort {
+    /// For deliveries outside the server, e.g. from the admin CLI.
+    pub fn never() -> Self {
+        Self(watch::channel(false).1)
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once deliveries must stop.
+    pub async fn aborted(&self) {
+        let mut receiver = self.0.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // Nothing can abort deliveries any more.
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Counts the requests being handled, so that shutdown can wait for them.
+#[derive(Clone, Default)]
+pub struct InFlightRequests(Arc<InFlight>);
+
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    drained: Notify,
+}
+
+impl InFlightRequests {
+    /// Counts a request until the returned guard is dropped.
+    fn start(&self) -> InFlightGuard {
+        self.0.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+
+    /// Completes once no request is being handled.
+    async fn drained(&self) {
+        loop {
+            // Created before the check, so a notification in between is
+            // not missed.
+            let drained = self.0.drained.notified();
+            if self.0.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+}
+
+struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+/// Completes on SIGTERM, sent by Heroku and Docker to stop a container, or
+/// on Ctrl-C.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
 }
 
@@ -66,28 +313,172 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
         .connect_lazy_with(configuration.with_db())
 }
 
+pub fn get_email_client(configuration: &EmailClientSettings) -> EmailClient {
+    let sender_email = configuration
+        .sender()
+        .expect("Invalid sender email address.");
+    EmailClient::new(
+        configuration.base_url.clone(),
+        sender_email,
+        configuration.auth_token.clone(),
+        configuration.timeout(),
+    )
+    .with_delivery_limits(
+        configuration.rate_limiter(),
+        configuration.max_concurrent_requests,
+        configuration.max_retries,
+    )
+    .with_batch_size(configuration.batch_size)
+}
+
+/// Tracking state, with the task writing its events to `pool`. Shut the
+/// flusher down before exiting, or buffered events are lost.
+pub fn get_tracking(
+    configuration: &TrackingSettings,
+    pool: PgPool,
+) -> (Tracking, TrackingEventFlusher) {
+    let (events, tracking_flusher) = TrackingEventBuffer::spawn(
+        pool,
+        configuration.buffer_capacity,
+        TRACKING_EVENTS_BATCH_SIZE,
+        configuration.flush_interval(),
+    );
+    let tracking = Tracking {
+        signer: TrackingSigner::new(configuration.signing_key.clone()),
+        open_tracking_enabled: configuration.open_tracking_enabled,
+        click_tracking_enabled: configuration.click_tracking_enabled,
+        events,
+    };
+    (tracking, tracking_flusher)
+}
+
+/// Everything request handlers get access to through `web::Data`.
+pub struct AppState {
+    pub db_pool: PgPool,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    pub webhook_secret: Secret<String>,
+    pub admin_api_token: Secret<String>,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub tracking: Tracking,
+    pub health: HealthSettings,
+    /// Not for handlers: counts their requests, for shutdown.
+    pub in_flight: InFlightRequests,
+    pub delivery_abort: DeliveryAbort,
+}
+
 pub fn run(
     listener: TcpListener,
-    db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    state: AppState,
+    serve_metrics: bool,
+    shutdown_timeout: Duration,
 ) -> Result<Server, std::io::Error> {
-    let db_pool = web::Data::new(db_pool);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let db_pool = web::Data::new(state.db_pool);
+    let email_client = Data::new(state.email_client);
+    let base_url = Data::new(ApplicationBaseUrl(state.base_url));
+    let webhook_secret = Data::new(WebhookSecret(state.webhook_secret));
+    let admin_api_token = Data::new(AdminApiToken(state.admin_api_token));
+    let trusted_proxies = Data::new(TrustedProxies(state.trusted_proxies));
+    let tracking = Data::new(state.tracking);
+    let health = Data::new(state.health);
+    let in_flight = state.in_flight;
+    let delivery_abort = Data::new(state.delivery_abort);
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap_fn(|request, service| {
+                // Set by `RequestIdRootSpanBuilder`, which runs first.
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .cloned()
+                    .unwrap_or_else(RequestId::generate);
+                let response = request_id.clone().scope(service.call(request));
+                async move {
+                    let response = response.await?.map_into_boxed_body();
+                    Ok(echo_request_id(response, &request_id))
+                }
+            })
+            .wrap_fn({
+                let in_flight = in_flight.clone();
+                move |request, service| {
+                    let in_flight = in_flight.start();
+                    let response = service.call(request);
+                    async move {
+                        let response = response.await;
+                        drop(in_flight);
+                        response
+                    }
+                }
+            })
+            .wrap_fn(|request, service| {
+                let timer = HttpRequestTimer::start(&request);
+                let response = service.call(request);
+                async move {
+                    let response = response.await;
+                    timer.finish(&response);
+                    response
+                }
+            })
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.service(export_metrics);
+                }
+            })
             .service(health_check)
+            .service(health_ready)
             .service(subscribe)
             .service(confirm)
+            .service(request_data_export)
+            .service(download_data_export)
             .service(publish_newsletter)
+            .service(get_issue_deliveries)
+            .service(get_issue_opens)
+            .service(get_issue_clicks)
+            .service(get_issue_stats)
+            .service(get_subscriber_report)
+            .service(get_audit_log)
+            .service(export_subscriber)
+            .service(erase_subscriber_data)
+            .service(create_suppression)
+            .service(delete_suppression)
+            .service(import_suppressions)
+            .service(receive_email_events)
+            .service(track_open)
+            .service(track_click)
             .app_data(db_pool.clone())
             .app_data(base_url.clone())
-            .app_data(Data::new(email_client.clone()))
+            .app_data(webhook_secret.clone())
+            .app_data(admin_api_token.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(delivery_abort.clone())
+            .app_data(tracking.clone())
+            .app_data(health.clone())
+            .app_data(email_client.clone())
     })
     .listen(listener)?
+    // `Application` handles signals, to coordinate shutdown across servers.
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .run();
 
     Ok(server)
 }
+
+/// Serves `/metrics` alone, for deployments exposing it on a private port.
+pub fn run_metrics(
+    listener: TcpListener,
+    db_pool: PgPool,
+    shutdown_timeout: Duration,
+) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let server =
+        HttpServer::new(move || App::new().service(export_metrics).app_data(db_pool.clone()))
+            .listen(listener)?
+            .disable_signals()
+            .shutdown_timeout(shutdown_timeout.as_secs())
+            .run();
+
+    Ok(server)
+}
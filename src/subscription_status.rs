@@ -0,0 +1,82 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Records the initial status of a subscriber who was just inserted.
+#[tracing::instrument(name = "Record the initial subscription status", skip(executor))]
+pub async fn record_initial_status(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+        VALUES ($1, NULL, $2, now())
+        "#,
+        subscriber_id,
+        status
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Moves a subscriber to `status`, recording the transition in
+/// `subscription_status_changes` within the same statement.
+///
+/// Returns `false`, and records nothing, if the subscriber does not exist or
+/// is already in `status`.
+#[tracing::instrument(name = "Change a subscription status", skip(executor))]
+pub async fn change_status(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        WITH previous AS (
+            SELECT id, status FROM subscriptions WHERE id = $1 FOR UPDATE
+        ), updated AS (
+            UPDATE subscriptions s SET status = $2
+            FROM previous p
+            WHERE s.id = p.id AND p.status IS DISTINCT FROM $2
+            RETURNING s.id, p.status AS from_status
+        )
+        INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+        SELECT id, from_status, $2, now() FROM updated
+        "#,
+        subscriber_id,
+        status
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Same as `change_status`, for the subscriber registered with `email`.
+#[tracing::instrument(name = "Change a subscription status by email", skip(executor, email))]
+pub async fn change_status_by_email(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    status: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        WITH previous AS (
+            SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE
+        ), updated AS (
+            UPDATE subscriptions s SET status = $2
+            FROM previous p
+            WHERE s.id = p.id AND p.status IS DISTINCT FROM $2
+            RETURNING s.id, p.status AS from_status
+        )
+        INSERT INTO subscription_status_changes (subscriber_id, from_status, to_status, changed_at)
+        SELECT id, from_status, $2, now() FROM updated
+        "#,
+        email,
+        status
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
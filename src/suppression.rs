@@ -0,0 +1,109 @@
+use sha2::{Digest, Sha256};
+use sqlx::PgExecutor;
+
+/// Why an address ended up on the suppression list.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Bounce,
+    Complaint,
+    Manual,
+    Legal,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Bounce => "bounce",
+            SuppressionReason::Complaint => "complaint",
+            SuppressionReason::Manual => "manual",
+            SuppressionReason::Legal => "legal",
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of the trimmed, lowercased address.
+///
+/// The only normalisation of addresses: it is stored with each subscriber
+/// as `subscriptions.email_hash`, rather than recomputed in SQL, whose
+/// `lower` and `trim` disagree with Rust's beyond ASCII.
+pub fn email_hash(email: &str) -> String {
+    let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    format!("{:x}", digest)
+}
+
+/// Returns the reason an address is suppressed, if it is.
+#[tracing::instrument(name = "Check the suppression list", skip(executor, email))]
+pub async fn suppression_reason(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT reason FROM suppressions WHERE email_hash = $1"#,
+        email_hash(email)
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.reason))
+}
+
+/// Adds an address to the suppression list, returning `false` if it was
+/// already there. The original reason is kept in that case.
+#[tracing::instrument(name = "Add a suppression", skip(executor, email))]
+pub async fn add_suppression(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    reason: SuppressionReason,
+    source: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_hash, reason, source, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        email_hash(email),
+        reason.as_str(),
+        source
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Removes an address from the suppression list, returning `false` if it
+/// was not there.
+#[tracing::instrument(name = "Remove a suppression", skip(executor, email))]
+pub async fn remove_suppression(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM suppressions WHERE email_hash = $1"#,
+        email_hash(email)
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::email_hash;
+
+    #[test]
+    fn email_hash_ignores_case_and_surrounding_whitespace() {
+        assert_eq!(
+            email_hash(" Reader@Example.com "),
+            email_hash("reader@example.com")
+        );
+    }
+
+    #[test]
+    fn email_hash_is_hex_encoded_sha256() {
+        assert_eq!(
+            email_hash("reader@example.com"),
+            "d108b279434fe1d54ac0f1da633564604b26c2e0e221d108b0fbadb87aba02c0"
+        );
+    }
+}
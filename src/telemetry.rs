@@ -1,9 +1,21 @@
+use crate::configuration::TelemetrySettings;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Sampler, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TracerProvider};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::WithExportConfig;
+use reqwest::header::HeaderMap;
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+
 /// # Implementation Notes
 ///
 /// We are using `impl Subscriber` as return type to avoid having to
@@ -12,10 +24,16 @@ use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 /// We need to explicitly call out that the returned subscriber is
 /// `Send` and `Sync` to make it possible to pass it to `init_subscriber`
 /// later on.
+///
+/// Spans are always given an OpenTelemetry context, so W3C `traceparent`
+/// headers are propagated whether or not `telemetry.otlp_endpoint` is set;
+/// they are only exported when it is. Exporting needs a Tokio runtime, and
+/// the tracer provider it installs is process-wide.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    telemetry: &TelemetrySettings,
 ) -> impl Subscriber + Sync + Send
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -23,13 +41,51 @@ where
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let tracer = get_tracer(telemetry).expect("Failed to build the OTLP exporter");
+    let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
 
     Registry::default()
         .with(env_filter)
+        .with(otel_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+/// Installs the process-wide tracer provider, batching spans to the OTLP
+/// endpoint when one is configured, and W3C trace context propagation.
+fn get_tracer(telemetry: &TelemetrySettings) -> Result<Tracer, TraceError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            telemetry.sampling_ratio,
+        ))))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            telemetry.service_name.clone(),
+        )]));
+
+    match &telemetry.otlp_endpoint {
+        Some(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(config)
+            .install_batch(opentelemetry::runtime::Tokio),
+        None => {
+            let provider = trace::TracerProvider::builder().with_config(config).build();
+            let tracer =
+                provider.versioned_tracer("zero2prod", Some(env!("CARGO_PKG_VERSION")), None);
+            // The tracer only holds a weak reference to its provider.
+            global::set_tracer_provider(provider);
+            Ok(tracer)
+        }
+    }
+}
+
 /// Register a subscriber as global default to process span data.
 ///
 /// It should only be called once!
@@ -37,3 +93,85 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Exports the spans still buffered for the OTLP endpoint. Blocks until
+/// they are sent, so call it once the servers have stopped.
+pub fn shutdown_tracer_provider() {
+    global::shutdown_tracer_provider();
+}
+
+/// W3C trace context headers for the current span, to attach to outgoing
+/// requests so the callee joins our trace.
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+/// Makes `span` a child of the W3C trace context found in `headers`, if any.
+pub fn set_parent_from_headers(span: &tracing::Span, headers: &actix_web::http::header::HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&RequestHeaders(headers)));
+    span.set_parent(parent);
+}
+
+struct RequestHeaders<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_subscriber, shutdown_tracer_provider};
+    use crate::configuration::TelemetrySettings;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    // The collector stand-in only sees spans once the batch processor is shut
+    // down, which blocks on work done by another runtime thread.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn spans_are_exported_to_the_otlp_endpoint() {
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .and(header("Content-Type", "application/x-protobuf"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let telemetry = TelemetrySettings {
+            service_name: "zero2prod-test".into(),
+            otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+            sampling_ratio: 1.0,
+        };
+
+        let subscriber = get_subscriber("test".into(), "info".into(), std::io::sink, &telemetry);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| tracing::info!("Inside the span"));
+        });
+        tokio::task::spawn_blocking(shutdown_tracer_provider)
+            .await
+            .unwrap();
+
+        // Protobuf encodes strings verbatim, so both names can be found in
+        // the raw body.
+        let received = collector.received_requests().await.unwrap();
+        let body = received
+            .iter()
+            .flat_map(|request| request.body.clone())
+            .collect::<Vec<u8>>();
+        let contains = |needle: &[u8]| body.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"Exported span"));
+        assert!(contains(b"zero2prod-test"));
+    }
+}
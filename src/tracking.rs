@@ -0,0 +1,566 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Bytes of the HMAC-SHA256 tag kept in a signed token.
+const TAG_LENGTH: usize = 16;
+
+/// The transparent 1x1 GIF served by the open tracking pixel.
+pub const TRANSPARENT_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Signs and verifies the tokens embedded in tracking URLs, so they can be
+/// trusted without a database lookup.
+#[derive(Clone)]
+pub struct TrackingSigner {
+    key: Secret<String>,
+}
+
+impl TrackingSigner {
+    pub fn new(key: Secret<String>) -> Self {
+        Self { key }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length")
+    }
+
+    /// Returns `<payload>.<tag>`, both URL-safe base64.
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let mut mac = self.mac();
+        mac.update(payload);
+        let tag = mac.finalize().into_bytes();
+        format!(
+            "{}.{}",
+            base64::encode_config(payload, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(&tag[..TAG_LENGTH], base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Returns the payload of a token produced by `sign`, or `None` if it was
+    /// tampered with or signed with another key.
+    pub fn verify(&self, token: &str) -> Option<Vec<u8>> {
+        let (payload, tag) = token.split_once('.')?;
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        let tag = base64::decode_config(tag, base64::URL_SAFE_NO_PAD).ok()?;
+        if tag.len() != TAG_LENGTH {
+            return None;
+        }
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_truncated_left(&tag).ok()?;
+        Some(payload)
+    }
+}
+
+/// Identifies the recipient of an issue in an open tracking URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenToken {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+}
+
+impl OpenToken {
+    pub fn sign(&self, signer: &TrackingSigner) -> String {
+        let mut payload = Vec::with_capacity(32);
+        payload.extend_from_slice(self.newsletter_issue_id.as_bytes());
+        payload.extend_from_slice(self.subscriber_id.as_bytes());
+        signer.sign(&payload)
+    }
+
+    pub fn verify(token: &str, signer: &TrackingSigner) -> Option<Self> {
+        let payload = signer.verify(token)?;
+        if payload.len() != 32 {
+            return None;
+        }
+        Some(Self {
+            newsletter_issue_id: Uuid::from_slice(&payload[..16]).ok()?,
+            subscriber_id: Uuid::from_slice(&payload[16..]).ok()?,
+        })
+    }
+}
+
+/// Identifies a link clicked by the recipient of an issue. The destination
+/// is part of the signed payload, so the redirect endpoint only ever sends
+/// readers to URLs we put in an issue ourselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClickToken {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: String,
+}
+
+impl ClickToken {
+    pub fn sign(&self, signer: &TrackingSigner) -> String {
+        let mut payload = Vec::with_capacity(32 + self.url.len());
+        payload.extend_from_slice(self.newsletter_issue_id.as_bytes());
+        payload.extend_from_slice(self.subscriber_id.as_bytes());
+        payload.extend_from_slice(self.url.as_bytes());
+        signer.sign(&payload)
+    }
+
+    pub fn verify(token: &str, signer: &TrackingSigner) -> Option<Self> {
+        let payload = signer.verify(token)?;
+        if payload.len() <= 32 {
+            return None;
+        }
+        let url = String::from_utf8(payload[32..].to_vec()).ok()?;
+        if !is_trackable_url(&url) {
+            return None;
+        }
+        Some(Self {
+            newsletter_issue_id: Uuid::from_slice(&payload[..16]).ok()?,
+            subscriber_id: Uuid::from_slice(&payload[16..32]).ok()?,
+            url,
+        })
+    }
+}
+
+/// Tracking configuration shared with request handlers.
+#[derive(Clone)]
+pub struct Tracking {
+    pub signer: TrackingSigner,
+    pub open_tracking_enabled: bool,
+    pub click_tracking_enabled: bool,
+    pub events: TrackingEventBuffer,
+}
+
+/// Renders the HTML of an issue for a given recipient.
+pub struct IssueRenderer<'a> {
+    pub html: &'a str,
+    pub newsletter_issue_id: Uuid,
+    pub base_url: &'a str,
+    /// Set when opens are tracked for this issue.
+    pub open_tracking: Option<&'a TrackingSigner>,
+    /// Set when clicks are tracked for this issue.
+    pub click_tracking: Option<&'a TrackingSigner>,
+}
+
+impl IssueRenderer<'_> {
+    /// Returns the recipient-specific HTML, or `None` if every recipient
+    /// gets the same `html`.
+    pub fn render(&self, subscriber_id: Uuid) -> Option<String> {
+        if self.open_tracking.is_none() && self.click_tracking.is_none() {
+            return None;
+        }
+        let mut html = match self.click_tracking {
+            Some(signer) => rewrite_links(self.html, |url| {
+                let token = ClickToken {
+                    newsletter_issue_id: self.newsletter_issue_id,
+                    subscriber_id,
+                    url: url.to_owned(),
+                }
+                .sign(signer);
+                format!("{}/t/c/{}", self.base_url, token)
+            }),
+            None => self.html.to_owned(),
+        };
+        if let Some(signer) = self.open_tracking {
+            let token = OpenToken {
+                newsletter_issue_id: self.newsletter_issue_id,
+                subscriber_id,
+            }
+            .sign(signer);
+            html.push_str(&format!(
+                "<img src=\"{}/t/o/{}.gif\" width=\"1\" height=\"1\" alt=\"\" />",
+                self.base_url, token
+            ));
+        }
+        Some(html)
+    }
+}
+
+/// Only plain web links are rewritten: `mailto:` and other schemes would
+/// break behind a redirect, and unsubscribe links must keep working even
+/// if tracking does not.
+fn is_trackable_url(url: &str) -> bool {
+    let lowercase = url.to_ascii_lowercase();
+    (lowercase.starts_with("http://") || lowercase.starts_with("https://"))
+        && !lowercase.contains("unsubscribe")
+}
+
+/// Replaces the `href` of every `<a>` tag in `html` pointing at a trackable
+/// URL with `rewrite(url)`.
+///
+/// This is a scanner rather than a parser: it understands quoted `href`
+/// attributes, which is what our issue templates use.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> String) -> String {
+    // ASCII lowercasing keeps byte offsets aligned with `html`.
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len());
+    let mut cursor = 0;
+    while let Some(tag_start) = find_anchor_tag(&lowercase, cursor) {
+        let tag_end = match lowercase[tag_start..].find('>') {
+            Some(offset) => tag_start + offset,
+            None => break,
+        };
+        if let Some((start, end)) = find_href(&lowercase, tag_start, tag_end) {
+            let url = html[start..end].replace("&amp;", "&");
+            if is_trackable_url(&url) {
+                rewritten.push_str(&html[cursor..start]);
+                rewritten.push_str(&rewrite(&url));
+                cursor = end;
+            }
+        }
+        rewritten.push_str(&html[cursor..tag_end]);
+        cursor = tag_end;
+    }
+    rewritten.push_str(&html[cursor..]);
+    rewritten
+}
+
+fn find_anchor_tag(lowercase: &str, from: usize) -> Option<usize> {
+    let mut from = from;
+    while let Some(offset) = lowercase[from..].find("<a") {
+        let start = from + offset;
+        let next = lowercase[start + 2..].chars().next();
+        if next.is_some_and(|c| c.is_ascii_whitespace()) {
+            return Some(start);
+        }
+        from = start + 2;
+    }
+    None
+}
+
+/// Returns the byte range of the quoted `href` value within a tag.
+fn find_href(lowercase: &str, tag_start: usize, tag_end: usize) -> Option<(usize, usize)> {
+    let tag = &lowercase[tag_start..tag_end];
+    let mut from = 0;
+    while let Some(offset) = tag[from..].find("href") {
+        let name_start = from + offset;
+        from = name_start + 4;
+        if !tag[..name_start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let rest = tag[from..].trim_start();
+        let rest = match rest.strip_prefix('=') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value_start = tag_end - rest.len() + 1;
+        let value_len = rest[1..].find(quote)?;
+        return Some((value_start, value_start + value_len));
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct OpenEvent {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub opened_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ClickEvent {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: String,
+    pub clicked_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum TrackingEvent {
+    Open(OpenEvent),
+    Click(ClickEvent),
+}
+
+/// Buffers tracking events in memory and writes them to Postgres in batches
+/// from a background task, keeping the tracking endpoints off the database.
+#[derive(Clone)]
+pub struct TrackingEventBuffer {
+    sender: mpsc::Sender<TrackingEvent>,
+}
+
+/// Controls the task spawned by `TrackingEventBuffer::spawn`.
+pub struct TrackingEventFlusher {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl TrackingEventFlusher {
+    /// Stops accepting events and waits for those already buffered to be
+    /// written.
+    pub async fn shutdown(self) {
+        // The task may be gone already, in which case there is nothing to do.
+        let _ = self.shutdown.send(());
+        if let Err(e) = self.handle.await {
+            tracing::error!(error.cause_chain = ?e, "The tracking event flusher failed");
+        }
+    }
+}
+
+impl TrackingEventBuffer {
+    /// Spawns the flushing task. It writes whatever is buffered every
+    /// `flush_interval`, or as soon as `max_batch_size` events are waiting,
+    /// and exits after a final flush once every buffer handle is dropped or
+    /// the flusher is shut down.
+    pub fn spawn(
+        pool: PgPool,
+        capacity: usize,
+        max_batch_size: usize,
+        flush_interval: std::time::Duration,
+    ) -> (Self, TrackingEventFlusher) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let handle = tokio::spawn(flush_tracking_events(
+            pool,
+            receiver,
+            shutdown_receiver,
+            max_batch_size.max(1),
+            flush_interval,
+        ));
+        (Self { sender }, TrackingEventFlusher { shutdown, handle })
+    }
+
+    /// Queues an event without waiting. Events are dropped, with a warning,
+    /// if the buffer is full or the flusher has shut down.
+    pub fn record(&self, event: TrackingEvent) {
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!("Dropping a tracking event, the buffer is full");
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::warn!("Dropping a tracking event, the buffer is shut down");
+            }
+        }
+    }
+}
+
+async fn flush_tracking_events(
+    pool: PgPool,
+    mut receiver: mpsc::Receiver<TrackingEvent>,
+    mut shutdown: oneshot::Receiver<()>,
+    max_batch_size: usize,
+    flush_interval: std::time::Duration,
+) {
+    let mut batch = Vec::with_capacity(max_batch_size);
+    let mut interval = tokio::time::interval(flush_interval);
+    let mut shutting_down = false;
+    loop {
+        let closed = tokio::select! {
+            // Closing the channel keeps the events already in it, so they
+            // are received, and flushed, before `recv` returns `None`.
+            _ = &mut shutdown, if !shutting_down => {
+                shutting_down = true;
+                receiver.close();
+                continue;
+            }
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    batch.push(event);
+                    if batch.len() < max_batch_size {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = interval.tick() => false,
+        };
+
+        if !batch.is_empty() {
+            let (mut opens, mut clicks) = (Vec::new(), Vec::new());
+            for event in batch.drain(..) {
+                match event {
+                    TrackingEvent::Open(event) => opens.push(event),
+                    TrackingEvent::Click(event) => clicks.push(event),
+                }
+            }
+            if !opens.is_empty() {
+                if let Err(e) = insert_open_events(&pool, &opens).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        events = opens.len(),
+                        "Failed to store a batch of open events",
+                    );
+                }
+            }
+            if !clicks.is_empty() {
+                if let Err(e) = insert_click_events(&pool, &clicks).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        events = clicks.len(),
+                        "Failed to store a batch of click events",
+                    );
+                }
+            }
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+#[tracing::instrument(name = "Store open events", skip_all, fields(events = events.len()))]
+async fn insert_open_events(pool: &PgPool, events: &[OpenEvent]) -> Result<(), sqlx::Error> {
+    let issue_ids: Vec<Uuid> = events.iter().map(|e| e.newsletter_issue_id).collect();
+    let subscriber_ids: Vec<Uuid> = events.iter().map(|e| e.subscriber_id).collect();
+    let opened_at: Vec<DateTime<Utc>> = events.iter().map(|e| e.opened_at).collect();
+    // Arrays cannot carry NULLs through sqlx, so a missing user agent
+    // travels as ''.
+    let user_agents: Vec<String> = events
+        .iter()
+        .map(|e| e.user_agent.clone().unwrap_or_default())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO open_events (newsletter_issue_id, subscriber_id, opened_at, user_agent)
+        SELECT t.newsletter_issue_id, t.subscriber_id, t.opened_at, NULLIF(t.user_agent, '')
+        FROM UNNEST($1::uuid[], $2::uuid[], $3::timestamptz[], $4::text[])
+            AS t(newsletter_issue_id, subscriber_id, opened_at, user_agent)
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        "#,
+        &issue_ids,
+        &subscriber_ids,
+        &opened_at,
+        &user_agents,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Store click events", skip_all, fields(events = events.len()))]
+async fn insert_click_events(pool: &PgPool, events: &[ClickEvent]) -> Result<(), sqlx::Error> {
+    let issue_ids: Vec<Uuid> = events.iter().map(|e| e.newsletter_issue_id).collect();
+    let subscriber_ids: Vec<Uuid> = events.iter().map(|e| e.subscriber_id).collect();
+    let urls: Vec<String> = events.iter().map(|e| e.url.clone()).collect();
+    let clicked_at: Vec<DateTime<Utc>> = events.iter().map(|e| e.clicked_at).collect();
+    let user_agents: Vec<String> = events
+        .iter()
+        .map(|e| e.user_agent.clone().unwrap_or_default())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO click_events (newsletter_issue_id, subscriber_id, url, clicked_at, user_agent)
+        SELECT t.newsletter_issue_id, t.subscriber_id, t.url, t.clicked_at, NULLIF(t.user_agent, '')
+        FROM UNNEST($1::uuid[], $2::uuid[], $3::text[], $4::timestamptz[], $5::text[])
+            AS t(newsletter_issue_id, subscriber_id, url, clicked_at, user_agent)
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        "#,
+        &issue_ids,
+        &subscriber_ids,
+        &urls,
+        &clicked_at,
+        &user_agents,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_links, ClickToken, OpenToken, TrackingSigner};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn signer(key: &str) -> TrackingSigner {
+        TrackingSigner::new(Secret::new(key.to_string()))
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let token = OpenToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+        };
+
+        let signed = token.sign(&signer("key"));
+
+        assert_eq!(OpenToken::verify(&signed, &signer("key")), Some(token));
+    }
+
+    #[test]
+    fn tokens_signed_with_another_key_are_rejected() {
+        let token = OpenToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+        };
+
+        let signed = token.sign(&signer("key"));
+
+        assert_eq!(OpenToken::verify(&signed, &signer("another key")), None);
+    }
+
+    #[test]
+    fn tampered_payloads_are_rejected() {
+        let signer = signer("key");
+        let signed = signer.sign(b"payload");
+        let (_, tag) = signed.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            base64::encode_config(b"forged", base64::URL_SAFE_NO_PAD),
+            tag
+        );
+
+        assert_eq!(signer.verify(&forged), None);
+        assert_eq!(signer.verify("not-a-token"), None);
+    }
+
+    #[test]
+    fn click_tokens_round_trip_with_their_destination() {
+        let token = ClickToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: "https://example.com/post?id=1&ref=mail".into(),
+        };
+
+        let signed = token.sign(&signer("key"));
+
+        assert_eq!(ClickToken::verify(&signed, &signer("key")), Some(token));
+    }
+
+    #[test]
+    fn click_tokens_to_untrackable_urls_are_rejected() {
+        let token = ClickToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: "javascript:alert(1)".into(),
+        };
+
+        let signed = token.sign(&signer("key"));
+
+        assert_eq!(ClickToken::verify(&signed, &signer("key")), None);
+    }
+
+    #[test]
+    fn web_links_are_rewritten() {
+        let html = r#"<p><a class="cta" href="https://example.com/a?x=1&amp;y=2">Read</a> <A HREF='http://example.com/b'>more</A></p>"#;
+
+        let rewritten = rewrite_links(html, |url| format!("tracked:{}", url));
+
+        assert_eq!(
+            rewritten,
+            r#"<p><a class="cta" href="tracked:https://example.com/a?x=1&y=2">Read</a> <A HREF='tracked:http://example.com/b'>more</A></p>"#
+        );
+    }
+
+    #[test]
+    fn mailto_unsubscribe_and_unquoted_links_are_left_alone() {
+        let html = concat!(
+            r#"<a href="mailto:editor@example.com">Write to us</a>"#,
+            r#"<a href="https://example.com/unsubscribe?token=abc">Unsubscribe</a>"#,
+            r#"<a data-href="https://example.com/data">Data</a>"#,
+            r#"<abbr title="x">y</abbr><a name="top">Top</a>"#,
+        );
+
+        let rewritten = rewrite_links(html, |url| format!("tracked:{}", url));
+
+        assert_eq!(rewritten, html);
+    }
+}
@@ -0,0 +1,108 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Length of the passwords generated for users who were not given one.
+const GENERATED_PASSWORD_LENGTH: usize = 24;
+
+/// Hashes a password with Argon2id, as a PHC string embedding its salt and
+/// parameters.
+pub fn compute_password_hash(password: &Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    // The parameters OWASP recommends for Argon2id.
+    let params = Params::new(15000, 2, 1, None).map_err(anyhow::Error::msg)?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(anyhow::Error::msg)?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// A random alphanumeric password.
+pub fn generate_password() -> Secret<String> {
+    Secret::new(
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_PASSWORD_LENGTH)
+            .map(char::from)
+            .collect(),
+    )
+}
+
+/// Creates a user, returning `None` if the username is taken.
+#[tracing::instrument(name = "Create a user", skip(executor, password_hash))]
+pub async fn create_user(
+    executor: impl PgExecutor<'_>,
+    username: &str,
+    password_hash: &Secret<String>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, created_at, updated_at)
+        VALUES ($1, $2, $3, now(), now())
+        ON CONFLICT (username) DO NOTHING
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Replaces a user's password hash, returning `None` if there is no such
+/// user.
+#[tracing::instrument(name = "Change a user's password", skip(executor, password_hash))]
+pub async fn change_password(
+    executor: impl PgExecutor<'_>,
+    username: &str,
+    password_hash: &Secret<String>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users SET password_hash = $2, updated_at = now()
+        WHERE username = $1
+        RETURNING user_id
+        "#,
+        username,
+        password_hash.expose_secret(),
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, generate_password};
+    use argon2::{Argon2, PasswordHash, PasswordVerifier};
+    use secrecy::{ExposeSecret, Secret};
+
+    #[test]
+    fn hashes_verify_against_their_password_only() {
+        let hash = compute_password_hash(&Secret::new("correct horse".into())).unwrap();
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+
+        assert_eq!(hash.algorithm.as_str(), "argon2id");
+        assert!(Argon2::default()
+            .verify_password(b"correct horse", &hash)
+            .is_ok());
+        assert!(Argon2::default()
+            .verify_password(b"battery staple", &hash)
+            .is_err());
+    }
+
+    #[test]
+    fn generated_passwords_are_long_and_distinct() {
+        let (first, second) = (generate_password(), generate_password());
+
+        assert_eq!(first.expose_secret().len(), 24);
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+}
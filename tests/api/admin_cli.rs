@@ -0,0 +1,434 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn delivery_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn subscribers_can_be_listed_by_status() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("first@example.com").await;
+    app.insert_confirmed_subscriber("second@example.com").await;
+    app.admin(&["subscribers", "unsubscribe", "second@example.com"])
+        .await
+        .unwrap();
+
+    let confirmed = app
+        .admin_json(&["subscribers", "list", "--status", "confirmed"])
+        .await;
+
+    let confirmed = confirmed.as_array().unwrap();
+    assert_eq!(confirmed.len(), 1);
+    assert_eq!(confirmed[0]["email"], "first@example.com");
+    assert_eq!(confirmed[0]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn tables_are_printed_by_default() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    let out = app.admin(&["subscribers", "list"]).await.unwrap();
+
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("id "), "{}", out);
+    assert!(lines[1].contains("reader@example.com"), "{}", out);
+}
+
+#[tokio::test]
+async fn status_changes_are_applied_once_and_audited() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let subscriber_id = subscriber_id.to_string();
+
+    let first = app
+        .admin_json(&["subscribers", "unsubscribe", &subscriber_id])
+        .await;
+    let second = app
+        .admin_json(&["subscribers", "unsubscribe", &subscriber_id])
+        .await;
+
+    assert_eq!(first["changed"], true);
+    assert_eq!(second["changed"], false);
+    let shown = app
+        .admin_json(&["subscribers", "show", &subscriber_id])
+        .await;
+    assert_eq!(shown["status"], "unsubscribed");
+    let audit = sqlx::query!("SELECT actor, action, target FROM audit_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].action, "subscriber.unsubscribed");
+    assert_eq!(audit[0].target.as_deref(), Some(subscriber_id.as_str()));
+    assert!(audit[0].actor.as_deref().unwrap().starts_with("cli:"));
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_gone() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    sqlx::query!(
+        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ('token', $1)",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let deleted = app
+        .admin_json(&["subscribers", "delete", "reader@example.com"])
+        .await;
+
+    assert_eq!(deleted["deleted"], true);
+    let error = app
+        .admin(&["subscribers", "show", "reader@example.com"])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("No subscriber"), "{}", error);
+    let tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+}
+
+#[tokio::test]
+async fn deleted_subscribers_leave_only_anonymised_deliveries() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(newsletter_request_body()).await;
+
+    let deleted = app
+        .admin_json(&["subscribers", "delete", "reader@example.com"])
+        .await;
+
+    assert_eq!(deleted["anonymised_deliveries"], 1);
+    let delivery = sqlx::query!("SELECT subscriber_id, subscriber_email FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(delivery.subscriber_id, subscriber_id);
+    assert!(delivery.subscriber_email.ends_with("@erased.invalid"));
+    let suppressions = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM suppressions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressions.count, 0);
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_gone_and_suppressed() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    let erased = app
+        .admin_json(&["subscribers", "erase", "reader@example.com"])
+        .await;
+
+    assert_eq!(erased["suppressed"], true);
+    assert_eq!(erased["anonymised_deliveries"], 0);
+    let error = app
+        .admin(&["subscribers", "show", "reader@example.com"])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("No subscriber"), "{}", error);
+    let entry = sqlx::query!("SELECT actor FROM audit_log WHERE action = 'subscriber.erased'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(entry.actor.unwrap().starts_with("cli:"));
+}
+
+#[tokio::test]
+async fn issues_can_be_published_and_listed() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    std::fs::write(directory.join("issue.html"), "<p>Hello</p>").unwrap();
+    std::fs::write(directory.join("issue.txt"), "Hello").unwrap();
+
+    let published = app
+        .admin_json(&[
+            "issues",
+            "publish",
+            "--title",
+            "From the command line",
+            "--html-file",
+            directory.join("issue.html").to_str().unwrap(),
+            "--text-file",
+            directory.join("issue.txt").to_str().unwrap(),
+        ])
+        .await;
+
+    let issues = app.admin_json(&["issues", "list"]).await;
+    assert_eq!(
+        issues[0]["newsletter_issue_id"],
+        published["newsletter_issue_id"]
+    );
+    assert_eq!(issues[0]["title"], "From the command line");
+    assert_eq!(issues[0]["deliveries"], 1);
+    assert_eq!(delivery_status(&app).await, "sent");
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn failed_deliveries_can_be_inspected_and_retried() {
+    let app = spawn_app_with(|c| c.email_client.max_retries = 0).await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(newsletter_request_body()).await;
+    assert_eq!(delivery_status(&app).await, "failed");
+
+    let queue = app.admin_json(&["queue", "inspect"]).await;
+    assert_eq!(queue[0]["failed"], 1);
+
+    let retried = app.admin_json(&["queue", "retry-failed"]).await;
+
+    assert_eq!(retried[0]["retried"], 1);
+    assert_eq!(retried[0]["error"], serde_json::Value::Null);
+    assert_eq!(delivery_status(&app).await, "sent");
+    assert_eq!(
+        app.admin_json(&["queue", "inspect"]).await,
+        serde_json::json!([])
+    );
+}
+
+#[tokio::test]
+async fn stale_queued_and_missing_deliveries_are_retried() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("queued@example.com").await;
+    let missing = app.insert_confirmed_subscriber("missing@example.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(newsletter_request_body()).await;
+    // As if publishing had stopped an hour ago, before one delivery was sent
+    // and another recorded.
+    sqlx::query("UPDATE subscriptions SET subscribed_at = now() - interval '3 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE newsletter_issues SET published_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "UPDATE issue_deliveries SET status = 'queued', updated_at = now() - interval '2 hours'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query("DELETE FROM issue_deliveries WHERE subscriber_id = $1")
+        .bind(missing)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let retried = app.admin_json(&["queue", "retry-failed"]).await;
+
+    assert_eq!(retried[0]["retried"], 2);
+    let statuses: Vec<String> = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.status)
+        .collect();
+    assert_eq!(statuses, ["sent", "sent"]);
+}
+
+#[tokio::test]
+async fn recently_queued_deliveries_are_not_retried() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(newsletter_request_body()).await;
+    // Still being published.
+    sqlx::query("UPDATE issue_deliveries SET status = 'queued'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let retried = app.admin_json(&["queue", "retry-failed"]).await;
+
+    assert_eq!(retried, serde_json::json!([]));
+    assert_eq!(delivery_status(&app).await, "queued");
+}
+
+#[tokio::test]
+async fn cancelled_deliveries_are_not_retried() {
+    let app = spawn_app_with(|c| c.email_client.max_retries = 0).await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(newsletter_request_body()).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+        .to_string();
+
+    let cancelled = app
+        .admin_json(&["issues", "cancel", &newsletter_issue_id])
+        .await;
+
+    assert_eq!(cancelled["cancelled"], 1);
+    let retried = app.admin_json(&["queue", "retry-failed"]).await;
+    assert_eq!(retried, serde_json::json!([]));
+    assert_eq!(delivery_status(&app).await, "cancelled");
+}
+
+#[tokio::test]
+async fn users_get_a_generated_password_on_creation_and_reset() {
+    let app = spawn_app().await;
+
+    let created = app.admin_json(&["users", "create", "admin"]).await;
+    let first_hash = sqlx::query!("SELECT password_hash FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash;
+    let reset = app.admin_json(&["users", "reset-password", "admin"]).await;
+    let second_hash = sqlx::query!("SELECT password_hash FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash;
+
+    assert_eq!(created["username"], "admin");
+    assert_eq!(created["user_id"], reset["user_id"]);
+    assert_eq!(created["password"].as_str().unwrap().len(), 24);
+    assert_ne!(created["password"], reset["password"]);
+    assert!(first_hash.starts_with("$argon2id$"));
+    assert!(!first_hash.contains(created["password"].as_str().unwrap()));
+    assert_ne!(first_hash, second_hash);
+}
+
+#[tokio::test]
+async fn usernames_are_unique() {
+    let app = spawn_app().await;
+    app.admin(&["users", "create", "admin"]).await.unwrap();
+
+    let error = app.admin(&["users", "create", "admin"]).await.unwrap_err();
+
+    assert!(error.to_string().contains("is taken"), "{}", error);
+}
+
+#[tokio::test]
+async fn seeding_fills_an_empty_database_reproducibly() {
+    let first = spawn_app().await;
+    let second = spawn_app().await;
+    let args = [
+        "seed",
+        "--subscribers",
+        "300",
+        "--issues",
+        "6",
+        "--seed",
+        "11",
+        "--until",
+        "2022-10-01T00:00:00Z",
+    ];
+
+    let seeded = first.admin_json(&args).await;
+    second.admin(&args).await.unwrap();
+
+    assert_eq!(seeded["subscribers"], 300);
+    assert_eq!(seeded["issues"], 6);
+    let statuses = sqlx::query!(
+        r#"SELECT status, COUNT(*) AS "count!" FROM subscriptions GROUP BY status ORDER BY status"#
+    )
+    .fetch_all(&first.db_pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = statuses.iter().map(|r| r.status.as_str()).collect();
+    assert_eq!(
+        statuses,
+        [
+            "bounced",
+            "confirmed",
+            "pending_confirmation",
+            "unsubscribed"
+        ]
+    );
+    let fingerprint = |app: &TestApp| {
+        let pool = app.db_pool.clone();
+        async move {
+            sqlx::query!(
+                r#"
+                SELECT
+                    (SELECT string_agg(email || status, ',' ORDER BY id) FROM subscriptions) AS subscribers,
+                    (SELECT COUNT(*) FROM issue_deliveries) AS deliveries,
+                    (SELECT COUNT(*) FROM open_events) AS opens
+                "#
+            )
+            .fetch_one(&pool)
+            .await
+            .map(|r| (r.subscribers, r.deliveries, r.opens))
+            .unwrap()
+        }
+    };
+    assert_eq!(fingerprint(&first).await, fingerprint(&second).await);
+}
+
+#[tokio::test]
+async fn seeding_refuses_a_database_with_subscribers() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    let error = app
+        .admin(&["seed", "--subscribers", "10"])
+        .await
+        .unwrap_err();
+
+    assert!(
+        error.to_string().contains("already has subscribers"),
+        "{}",
+        error
+    );
+}
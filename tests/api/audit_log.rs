@@ -0,0 +1,202 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::suppression::email_hash;
+
+async fn audit_entries(app: &TestApp, query: &str) -> Vec<serde_json::Value> {
+    let response = app.get_audit_log(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["entries"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_is_audited() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .header("X-Request-Id", "publish-audit")
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let entries = audit_entries(&app, "action=newsletter.published").await;
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["target"], body["newsletter_issue_id"]);
+    assert_eq!(entry["request_id"], "publish-audit");
+    assert_eq!(entry["ip"], "127.0.0.1");
+    assert!(entry["actor"].is_null());
+    assert_eq!(entry["details"]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn suppression_changes_are_audited_without_the_address() {
+    let app = spawn_app().await;
+    let email = "reader@example.com";
+
+    app.post_suppression(serde_json::json!({ "email": email, "reason": "manual" }))
+        .await;
+    app.delete_suppression(email).await;
+
+    let entries = audit_entries(&app, "").await;
+    let actions: Vec<_> = entries
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(actions, ["suppression.removed", "suppression.added"]);
+    for entry in &entries {
+        assert_eq!(entry["target"], email_hash(email));
+        assert!(!entry.to_string().contains(email));
+    }
+    assert_eq!(entries[1]["details"]["reason"], "manual");
+}
+
+#[tokio::test]
+async fn removing_an_unknown_suppression_is_not_audited() {
+    let app = spawn_app().await;
+
+    let response = app.delete_suppression("reader@example.com").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(audit_entries(&app, "").await.is_empty());
+}
+
+#[tokio::test]
+async fn suppression_imports_are_audited_once() {
+    let app = spawn_app().await;
+
+    app.import_suppressions(serde_json::json!({
+        "suppressions": [
+            { "email": "a@example.com", "reason": "bounce" },
+            { "email": "b@example.com", "reason": "complaint" },
+        ]
+    }))
+    .await;
+
+    let entries = audit_entries(&app, "action=suppressions.imported").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["details"]["entries"], 2);
+    assert_eq!(entries[0]["details"]["added"], 2);
+}
+
+#[tokio::test]
+async fn audit_log_entries_are_paginated_newest_first() {
+    let app = spawn_app().await;
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        app.post_suppression(serde_json::json!({ "email": email, "reason": "manual" }))
+            .await;
+    }
+
+    let first_page = audit_entries(&app, "page=1&page_size=2").await;
+    let second_page = audit_entries(&app, "page=2&page_size=2").await;
+
+    assert_eq!(first_page.len(), 2);
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(first_page[0]["target"], email_hash("c@example.com"));
+    assert_eq!(second_page[0]["target"], email_hash("a@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_audit_log_queries_are_rejected() {
+    let app = spawn_app().await;
+
+    for query in [
+        "page=0",
+        "page_size=0",
+        "page_size=501",
+        "from=2022-02-01T00:00:00Z&to=2022-01-01T00:00:00Z",
+    ] {
+        let response = app.get_audit_log(query).await;
+        assert_eq!(response.status().as_u16(), 400, "Query: {}", query);
+    }
+}
+
+#[tokio::test]
+async fn the_audit_log_is_append_only() {
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({ "email": "a@example.com", "reason": "manual" }))
+        .await;
+
+    let update = sqlx::query("UPDATE audit_log SET action = 'tampered'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
+
+#[tokio::test]
+async fn the_audit_log_requires_the_admin_api_token() {
+    let app = spawn_app().await;
+
+    for request in [
+        reqwest::Client::new().get(format!("{}/admin/audit", app.address)),
+        reqwest::Client::new()
+            .get(format!("{}/admin/audit", app.address))
+            .bearer_auth("not-the-token"),
+    ] {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "unauthorized");
+    }
+}
+
+/// Adds a suppression as if through a proxy that forwarded it for
+/// `203.0.113.7`, returning its audit log entry.
+async fn audit_forwarded_request(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", app.address))
+        .bearer_auth(&app.admin_api_token)
+        .header("X-Forwarded-For", "203.0.113.7")
+        .json(&serde_json::json!({ "email": "reader@example.com", "reason": "manual" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let entries = audit_entries(app, "action=suppression.added").await;
+    assert_eq!(entries.len(), 1);
+    entries[0].clone()
+}
+
+#[tokio::test]
+async fn forwarding_headers_from_untrusted_peers_are_ignored() {
+    let app = spawn_app().await;
+
+    let entry = audit_forwarded_request(&app).await;
+
+    assert_eq!(entry["ip"], "127.0.0.1");
+    assert!(entry["forwarded_for"].is_null());
+}
+
+#[tokio::test]
+async fn addresses_forwarded_by_trusted_proxies_are_recorded_separately() {
+    let app =
+        spawn_app_with(|c| c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()])
+            .await;
+
+    let entry = audit_forwarded_request(&app).await;
+
+    assert_eq!(entry["ip"], "127.0.0.1");
+    assert_eq!(entry["forwarded_for"], "203.0.113.7");
+}
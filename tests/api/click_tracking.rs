@@ -0,0 +1,168 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const HTML: &str = concat!(
+    r#"<p><a href="https://example.com/post?id=1&amp;ref=mail">Read the post</a></p>"#,
+    r#"<p><a href="https://example.com/about">About</a></p>"#,
+    r#"<p><a href="mailto:editor@example.com">Write to us</a></p>"#,
+    r#"<p><a href="https://example.com/unsubscribe">Unsubscribe</a></p>"#,
+);
+
+async fn publish_issue(app: &TestApp, track_clicks: bool) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": HTML,
+            },
+            "track_clicks": track_clicks,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn sent_html(app: &TestApp) -> String {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["htmlContent"].as_str().unwrap().to_owned()
+}
+
+/// Paths of the tracked links in `html`, in document order.
+fn tracked_link_paths(html: &str) -> Vec<String> {
+    html.match_indices("/t/c/")
+        .map(|(start, _)| {
+            let end = start + html[start..].find('"').unwrap();
+            html[start..end].to_owned()
+        })
+        .collect()
+}
+
+/// Click events are written in the background, so poll until they land.
+async fn wait_for_clicks(
+    app: &TestApp,
+    newsletter_issue_id: &str,
+    total: i64,
+) -> serde_json::Value {
+    for _ in 0..50 {
+        let report: serde_json::Value = app
+            .get_issue_clicks(newsletter_issue_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        if report["total_clicks"] == total {
+            return report;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("Click events were not recorded in time");
+}
+
+#[tokio::test]
+async fn only_web_links_are_rewritten() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    publish_issue(&app, true).await;
+
+    let html = sent_html(&app).await;
+    assert_eq!(tracked_link_paths(&html).len(), 2);
+    assert!(!html.contains("https://example.com/post"));
+    assert!(html.contains(r#"href="mailto:editor@example.com""#));
+    assert!(html.contains(r#"href="https://example.com/unsubscribe""#));
+}
+
+#[tokio::test]
+async fn tracked_links_redirect_to_the_original_url_and_record_the_click() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    let newsletter_issue_id = publish_issue(&app, true).await;
+    let links = tracked_link_paths(&sent_html(&app).await);
+
+    let response = app.get_tracked_link(&links[0]).await;
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["location"],
+        "https://example.com/post?id=1&ref=mail"
+    );
+    let report = wait_for_clicks(&app, &newsletter_issue_id, 1).await;
+    assert_eq!(
+        report["links"][0]["url"],
+        "https://example.com/post?id=1&ref=mail"
+    );
+}
+
+#[tokio::test]
+async fn clicks_are_counted_per_link() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    let newsletter_issue_id = publish_issue(&app, true).await;
+    let links = tracked_link_paths(&sent_html(&app).await);
+
+    app.get_tracked_link(&links[0]).await;
+    app.get_tracked_link(&links[0]).await;
+    app.get_tracked_link(&links[1]).await;
+
+    let report = wait_for_clicks(&app, &newsletter_issue_id, 3).await;
+    assert_eq!(report["unique_clicks"], 1);
+    let links = report["links"].as_array().unwrap();
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0]["url"], "https://example.com/post?id=1&ref=mail");
+    assert_eq!(links[0]["total_clicks"], 2);
+    assert_eq!(links[0]["unique_clicks"], 1);
+    assert_eq!(links[1]["url"], "https://example.com/about");
+    assert_eq!(links[1]["total_clicks"], 1);
+}
+
+#[tokio::test]
+async fn links_are_left_alone_unless_the_issue_opts_in() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    publish_issue(&app, false).await;
+
+    assert_eq!(sent_html(&app).await, HTML);
+}
+
+#[tokio::test]
+async fn tampered_click_tokens_are_a_404() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    publish_issue(&app, true).await;
+    let link = tracked_link_paths(&sent_html(&app).await).remove(0);
+    let (_, tag) = link.rsplit_once('.').unwrap();
+    let forged_payload = base64::encode_config(
+        [[0u8; 32].as_slice(), b"https://evil.example.com"].concat(),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    let response = app
+        .get_tracked_link(&format!("/t/c/{}.{}", forged_payload, tag))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(response.headers().get("location").is_none());
+}
+
+#[tokio::test]
+async fn clicks_of_an_unknown_issue_are_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_clicks("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
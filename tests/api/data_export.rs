@@ -0,0 +1,279 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Subscribes through the API, returning the token from the confirmation
+/// email.
+async fn subscribe(app: &TestApp) -> String {
+    let _mock = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = reqwest::Url::parse(&app.get_subscription_link(email_request)).unwrap();
+    confirmation_link
+        .query_pairs()
+        .find(|(name, _)| name == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+/// Requests an export, returning the link emailed for it.
+async fn export_link(app: &TestApp, subscription_token: &str) -> String {
+    let _mock = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.request_data_export(subscription_token).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app.email_server.received_requests().await.unwrap();
+    app.get_subscription_link(email_request.last().unwrap())
+}
+
+#[tokio::test]
+async fn subscribers_receive_their_data_through_an_emailed_link() {
+    let app = spawn_app().await;
+    let subscription_token = subscribe(&app).await;
+
+    let link = export_link(&app, &subscription_token).await;
+    let response = reqwest::get(&link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .headers()
+        .get("Content-Disposition")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let body = response.text().await.unwrap();
+    assert!(!body.contains(&subscription_token), "{}", body);
+    let export: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(export["subscriber"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["subscriber"]["status"], "pending_confirmation");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        export["status_changes"][0]["to_status"],
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn exports_are_not_sent_for_unknown_subscription_tokens() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.request_data_export("not-a-token").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn export_links_expire() {
+    let app = spawn_app().await;
+    let subscription_token = subscribe(&app).await;
+    let link = export_link(&app, &subscription_token).await;
+    sqlx::query!("UPDATE data_exports SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(&link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "export_link_expired");
+}
+
+#[tokio::test]
+async fn no_new_export_link_is_sent_while_one_is_pending() {
+    let app = spawn_app().await;
+    let subscription_token = subscribe(&app).await;
+    export_link(&app, &subscription_token).await;
+
+    let response = {
+        let _mock = Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.request_data_export(&subscription_token).await
+    };
+
+    assert_eq!(response.status().as_u16(), 409);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "export_link_pending");
+
+    // Once the link expires, a new one can be requested.
+    sqlx::query!("UPDATE data_exports SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    export_link(&app, &subscription_token).await;
+}
+
+#[tokio::test]
+async fn export_requests_must_be_posted() {
+    let app = spawn_app().await;
+    let subscription_token = subscribe(&app).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions/me/export", app.address))
+        .query(&[("subscription_token", &subscription_token)])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn unknown_export_links_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/me/export/download?export_token=guess",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn admins_can_export_a_subscriber_with_their_history() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let other_id = app.insert_confirmed_subscriber("other@example.com").await;
+    let newsletter_issue_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, published_at)
+        VALUES ($1, 'Issue', 'text', '<p>html</p>', now())
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    for (id, email) in [
+        (subscriber_id, "reader@example.com"),
+        (other_id, "other@example.com"),
+    ] {
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_deliveries
+                (newsletter_issue_id, subscriber_id, subscriber_email, status, created_at, updated_at)
+            VALUES ($1, $2, $3, 'delivered', now(), now())
+            "#,
+            newsletter_issue_id,
+            id,
+            email
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO open_events (newsletter_issue_id, subscriber_id, opened_at, user_agent)
+            VALUES ($1, $2, now(), 'test-mail-client')
+            "#,
+            newsletter_issue_id,
+            id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    let response = app.get_subscriber_export(&subscriber_id.to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["id"], subscriber_id.to_string());
+    assert_eq!(export["deliveries"].as_array().unwrap().len(), 1);
+    assert_eq!(export["deliveries"][0]["title"], "Issue");
+    assert_eq!(export["opens"].as_array().unwrap().len(), 1);
+    assert_eq!(export["opens"][0]["user_agent"], "test-mail-client");
+    let audit = sqlx::query!("SELECT action, target FROM audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.action, "subscriber.exported");
+    assert_eq!(audit.target, Some(subscriber_id.to_string()));
+}
+
+#[tokio::test]
+async fn exports_include_email_events_whatever_the_case_of_their_address() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    app.post_email_event(
+        serde_json::json!({
+            "event": "opened",
+            "email": "Reader@Example.COM",
+            "message-id": "<abc@brevo>",
+        }),
+        &app.webhook_secret,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app.get_subscriber_export(&subscriber_id.to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["email_events"].as_array().unwrap().len(), 1);
+    assert_eq!(export["email_events"][0]["event"], "opened");
+}
+
+#[tokio::test]
+async fn admin_exports_require_the_admin_api_token() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let url = format!("{}/admin/subscribers/{}/export", app.address, subscriber_id);
+
+    for request in [
+        reqwest::Client::new().get(&url),
+        reqwest::Client::new()
+            .get(&url)
+            .bearer_auth("not-the-token"),
+    ] {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "unauthorized");
+    }
+    let audit_entries = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit_entries.count, 0);
+}
+
+#[tokio::test]
+async fn exporting_an_unknown_subscriber_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_subscriber_export(&uuid::Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
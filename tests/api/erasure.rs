@@ -0,0 +1,312 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::suppression::email_hash;
+
+/// Sends an issue to two confirmed subscribers, who both open it, click a
+/// link and unsubscribe, and gives the reader a confirmation token. Returns
+/// the issue id and the reader's id.
+async fn issue_with_activity(app: &TestApp) -> (Uuid, Uuid) {
+    let reader_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let other_id = app.insert_confirmed_subscriber("other@example.com").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ('reader-token', $1)
+        "#,
+        reader_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, published_at)
+        VALUES ($1, 'Issue', 'text', '<p>html</p>', now())
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    for (id, email) in [
+        (reader_id, "reader@example.com"),
+        (other_id, "other@example.com"),
+    ] {
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, subscriber_email,
+                status, provider_message_id, created_at, updated_at)
+            VALUES ($1, $2, $3, 'delivered', $3, now(), now())
+            "#,
+            newsletter_issue_id,
+            id,
+            email
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO open_events (newsletter_issue_id, subscriber_id, opened_at, user_agent)
+            VALUES ($1, $2, now(), 'test-mail-client')
+            "#,
+            newsletter_issue_id,
+            id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO click_events
+                (newsletter_issue_id, subscriber_id, url, clicked_at, user_agent)
+            VALUES ($1, $2, 'https://example.com/', now(), 'test-mail-client')
+            "#,
+            newsletter_issue_id,
+            id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO email_events (email_event_id, event, email, email_hash,
+                newsletter_issue_id, reason, occurred_at, received_at)
+            VALUES ($1, 'unsubscribed', $2, $3, $4, $2, now(), now())
+            "#,
+            Uuid::new_v4(),
+            email,
+            email_hash(email),
+            newsletter_issue_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    (newsletter_issue_id, reader_id)
+}
+
+async fn stats_counts(app: &TestApp, newsletter_issue_id: Uuid) -> serde_json::Value {
+    let response = app
+        .get_issue_stats(&newsletter_issue_id.to_string(), "")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let stats: serde_json::Value = response.json().await.unwrap();
+    stats["counts"].clone()
+}
+
+/// Rows anywhere that still mention the subscriber by id or address.
+async fn traces_of(app: &TestApp, subscriber_id: Uuid, email: &str) -> i64 {
+    sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions WHERE id = $1 OR email = $2)
+            + (SELECT COUNT(*) FROM subscription_tokens WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM subscription_status_changes WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM issue_deliveries
+                WHERE subscriber_id = $1 OR subscriber_email = $2
+                    OR provider_message_id = $2 OR failure_reason = $2)
+            + (SELECT COUNT(*) FROM open_events WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM click_events WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM email_events WHERE email = $2 OR reason = $2)
+            AS "traces!"
+        "#,
+        subscriber_id,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .traces
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_removes_them_but_keeps_issue_stats() {
+    let app = spawn_app().await;
+    let (newsletter_issue_id, reader_id) = issue_with_activity(&app).await;
+    let counts_before = stats_counts(&app, newsletter_issue_id).await;
+
+    let response = app.erase_subscriber(&reader_id.to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let erasure: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(erasure["anonymised_deliveries"], 1);
+    assert_eq!(erasure["anonymised_opens"], 1);
+    assert_eq!(erasure["anonymised_clicks"], 1);
+    assert_eq!(erasure["anonymised_email_events"], 1);
+    assert_eq!(erasure["suppressed"], true);
+    assert_eq!(traces_of(&app, reader_id, "reader@example.com").await, 0);
+    assert_eq!(stats_counts(&app, newsletter_issue_id).await, counts_before);
+    assert_eq!(counts_before["opened"], 2);
+    assert_eq!(counts_before["unsubscribed"], 2);
+}
+
+#[tokio::test]
+async fn erasure_leaves_other_subscribers_alone() {
+    let app = spawn_app().await;
+    let (_, reader_id) = issue_with_activity(&app).await;
+
+    app.erase_subscriber(&reader_id.to_string()).await;
+
+    let other = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_deliveries
+                WHERE subscriber_email = 'other@example.com') AS "deliveries!",
+            (SELECT COUNT(*) FROM email_events
+                WHERE email = 'other@example.com') AS "email_events!",
+            (SELECT COUNT(*) FROM open_events WHERE user_agent IS NOT NULL) AS "opens!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(other.deliveries, 1);
+    assert_eq!(other.email_events, 1);
+    assert_eq!(other.opens, 1);
+}
+
+#[tokio::test]
+async fn erased_addresses_are_suppressed() {
+    let app = spawn_app().await;
+    let reader_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.erase_subscriber(&reader_id.to_string()).await;
+
+    let suppression = sqlx::query!("SELECT email_hash, reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        suppression.email_hash,
+        zero2prod::suppression::email_hash("reader@example.com")
+    );
+    assert_eq!(suppression.reason, "legal");
+    assert_eq!(suppression.source, "erasure");
+    // Signing up again does not bring the address back into mailings.
+    let response = app
+        .post_subscriptions("name=reader&email=reader%40example.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn erasures_are_audited_without_the_address() {
+    let app = spawn_app().await;
+    let reader_id = app.insert_confirmed_subscriber("reader@example.com").await;
+
+    app.erase_subscriber(&reader_id.to_string()).await;
+
+    let entry = sqlx::query!(
+        r#"SELECT action, target, details FROM audit_log WHERE action = 'subscriber.erased'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.target, Some(reader_id.to_string()));
+    assert_eq!(entry.details["suppressed"], true);
+    assert!(!entry.details.to_string().contains("reader@"));
+}
+
+#[tokio::test]
+async fn failed_erasures_change_nothing() {
+    let app = spawn_app().await;
+    let (newsletter_issue_id, reader_id) = issue_with_activity(&app).await;
+    // Fail the erasure half-way, once the tracking rows are anonymised.
+    sqlx::query(
+        r#"
+        CREATE FUNCTION refuse() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'refused';
+        END;
+        $$ LANGUAGE plpgsql;
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE TRIGGER refuse BEFORE DELETE ON subscriptions FOR EACH ROW EXECUTE FUNCTION refuse()",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.erase_subscriber(&reader_id.to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions WHERE id = $1) AS "subscriptions!",
+            (SELECT COUNT(*) FROM subscription_tokens WHERE subscriber_id = $1) AS "tokens!",
+            (SELECT COUNT(*) FROM issue_deliveries WHERE subscriber_id = $1) AS "deliveries!",
+            (SELECT COUNT(*) FROM open_events
+                WHERE subscriber_id = $1 AND user_agent IS NOT NULL) AS "opens!",
+            (SELECT COUNT(*) FROM email_events
+                WHERE email = 'reader@example.com' AND newsletter_issue_id = $2)
+                AS "email_events!",
+            (SELECT COUNT(*) FROM suppressions) AS "suppressions!",
+            (SELECT COUNT(*) FROM audit_log) AS "audit_entries!"
+        "#,
+        reader_id,
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 1);
+    assert_eq!(remaining.tokens, 1);
+    assert_eq!(remaining.deliveries, 1);
+    assert_eq!(remaining.opens, 1);
+    assert_eq!(remaining.email_events, 1);
+    assert_eq!(remaining.suppressions, 0);
+    assert_eq!(remaining.audit_entries, 0);
+}
+
+#[tokio::test]
+async fn erasing_an_unknown_subscriber_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.erase_subscriber(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn erasure_requires_the_admin_api_token() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let url = format!("{}/admin/subscribers/{}", app.address, subscriber_id);
+
+    for request in [
+        reqwest::Client::new().delete(&url),
+        reqwest::Client::new()
+            .delete(&url)
+            .bearer_auth("not-the-token"),
+    ] {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "unauthorized");
+    }
+    let subscriptions = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(subscriptions.count, 1);
+}
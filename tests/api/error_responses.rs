@@ -0,0 +1,101 @@
+use crate::helpers::spawn_app;
+
+async fn problem(response: reqwest::Response) -> serde_json::Value {
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], request_id);
+    body
+}
+
+#[tokio::test]
+async fn invalid_subscriptions_report_every_invalid_field() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=%20&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body = problem(response).await;
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["code"], "invalid_subscription");
+    let fields: Vec<_> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["name", "email"]);
+}
+
+#[tokio::test]
+async fn a_single_invalid_field_is_reported_alone() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=definitely-not-an-email".into())
+        .await;
+
+    let body = problem(response).await;
+    assert_eq!(body["errors"].as_array().unwrap().len(), 1);
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert!(body["errors"][0]["message"].is_string());
+}
+
+#[tokio::test]
+async fn unknown_confirmation_tokens_get_a_stable_code() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let body = problem(response).await;
+    assert_eq!(body["code"], "invalid_confirmation_token");
+}
+
+#[tokio::test]
+async fn extractor_errors_are_reported_as_problems() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body = problem(response).await;
+    assert_eq!(body["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn unexpected_errors_do_not_leak_their_cause() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 500);
+    let body = problem(response).await;
+    assert_eq!(body["code"], "internal_error");
+    let text = body.to_string();
+    assert!(!text.contains("subscription_token"));
+    assert!(!text.contains("Failed to"));
+}
@@ -1,4 +1,5 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use zero2prod::configuration::EmailProviderCheck;
 
 #[tokio::test]
 async fn health_check_test() {
@@ -14,3 +15,90 @@ async fn health_check_test() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn readiness_reports_every_check_when_dependencies_are_up() {
+    let app = spawn_app().await;
+
+    let response = app.get_readiness().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["checks"]["database"]["healthy"], true);
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["healthy"], true);
+    assert_eq!(body["checks"]["email_provider"]["healthy"], true);
+}
+
+#[tokio::test]
+async fn readiness_fails_with_a_503_when_the_database_is_unreachable() {
+    let app = spawn_app_with(|config| config.database.port = 1).await;
+
+    let response = app.get_readiness().await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["checks"]["database"]["healthy"], false);
+    assert_eq!(body["checks"]["database"]["status"], "unavailable");
+    assert!(!body.to_string().contains("refused"), "{}", body);
+}
+
+#[tokio::test]
+async fn readiness_fails_with_a_503_when_migrations_are_pending() {
+    let app = spawn_app().await;
+    let version = sqlx::query!("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations) RETURNING version")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .version;
+
+    let response = app.get_readiness().await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["migrations"]["status"], "unavailable");
+    assert!(!body.to_string().contains(&version.to_string()), "{}", body);
+}
+
+#[tokio::test]
+async fn an_unreachable_optional_email_provider_does_not_fail_readiness() {
+    let app = spawn_app_with(|config| {
+        config.email_client.base_url = "http://127.0.0.1:1".into();
+        config.health.email_provider_check = EmailProviderCheck::Optional;
+    })
+    .await;
+
+    let response = app.get_readiness().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["healthy"], false);
+    assert_eq!(body["checks"]["email_provider"]["required"], false);
+}
+
+#[tokio::test]
+async fn an_unreachable_required_email_provider_fails_readiness() {
+    let app = spawn_app_with(|config| {
+        config.email_client.base_url = "http://127.0.0.1:1".into();
+        config.health.email_provider_check = EmailProviderCheck::Required;
+    })
+    .await;
+
+    let response = app.get_readiness().await;
+
+    assert_eq!(response.status().as_u16(), 503);
+}
+
+#[tokio::test]
+async fn the_email_provider_check_can_be_disabled() {
+    let app = spawn_app_with(|config| {
+        config.health.email_provider_check = EmailProviderCheck::Disabled;
+    })
+    .await;
+
+    let body: serde_json::Value = app.get_readiness().await.json().await.unwrap();
+
+    assert!(body["checks"].get("email_provider").is_none());
+}
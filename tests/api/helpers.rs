@@ -1,11 +1,17 @@
+use clap::Parser;
 use linkify::LinkFinder;
 use once_cell::sync::Lazy;
 use reqwest::Url;
+use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::cli::Cli;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
 use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::suppression::email_hash;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 /**
@@ -17,12 +23,25 @@ great opportunity to introduce a useful crate into your toolkit.
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let telemetry = get_configuration()
+        .expect("Failed to read configuration")
+        .telemetry;
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            &telemetry,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            &telemetry,
+        );
         init_subscriber(subscriber);
     }
 });
@@ -32,12 +51,50 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub webhook_secret: String,
+    pub admin_api_token: String,
+    /// Set when `/metrics` is served on its own port.
+    pub metrics_address: Option<String>,
+    pub configuration: Settings,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<Result<(), std::io::Error>>>,
 }
 
 impl TestApp {
+    /// Runs `zero2prod-admin` with `args` against this app's database,
+    /// returning what it printed.
+    pub async fn admin(&self, args: &[&str]) -> Result<String, anyhow::Error> {
+        let cli =
+            Cli::try_parse_from(std::iter::once("zero2prod-admin").chain(args.iter().copied()))?;
+        let mut out = Vec::new();
+        zero2prod::cli::run(cli, &self.configuration, &mut out).await?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    /// Same as `admin`, with `--format json`, parsing the output.
+    pub async fn admin_json(&self, args: &[&str]) -> serde_json::Value {
+        let args: Vec<_> = args.iter().copied().chain(["--format", "json"]).collect();
+        let out = self.admin(&args).await.expect("The admin command failed");
+        serde_json::from_str(&out).unwrap()
+    }
+
+    /// Triggers a graceful shutdown, as SIGTERM would, and waits for the
+    /// application to stop.
+    pub async fn shutdown(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server) = self.server.take() {
+            server
+                .await
+                .expect("The application panicked")
+                .expect("The application failed to shut down");
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", self.address))
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -47,18 +104,226 @@ impl TestApp {
 
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_issue_deliveries(
+        &self,
+        newsletter_issue_id: &str,
+        query: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/deliveries?{}",
+                &self.address, newsletter_issue_id, query
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_opens(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/opens",
+                &self.address, newsletter_issue_id
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_tracking_pixel(&self, pixel_path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}{}", &self.address, pixel_path))
+            .header("User-Agent", "test-mail-client")
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_clicks(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/clicks",
+                &self.address, newsletter_issue_id
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_stats(
+        &self,
+        newsletter_issue_id: &str,
+        query: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/stats?{}",
+                &self.address, newsletter_issue_id, query
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Follows a tracked link without following its redirect.
+    pub async fn get_tracked_link(&self, link_path: &str) -> reqwest::Response {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(format!("{}{}", &self.address, link_path))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscriber_report(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/reports/subscribers?{}",
+                &self.address, query
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/audit?{}", &self.address, query))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_readiness(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        let address = self.metrics_address.as_ref().unwrap_or(&self.address);
+        reqwest::Client::new()
+            .get(format!("{}/metrics", address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Inserts a confirmed subscriber straight into the database, bypassing
+    /// the confirmation flow and its email validation.
+    pub async fn insert_confirmed_subscriber(&self, email: &str) -> Uuid {
+        let subscriber_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO subscriptions (id, email, email_hash, name, subscribed_at, status) \
+            VALUES ($1, $2, $3, 'subscriber', now(), 'confirmed')",
+        )
+        .bind(subscriber_id)
+        .bind(email)
+        .bind(email_hash(email))
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to insert confirmed subscriber");
+        subscriber_id
+    }
+
+    pub async fn post_email_event(
+        &self,
+        body: serde_json::Value,
+        bearer_token: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email-events", &self.address))
+            .bearer_auth(bearer_token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_suppression(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/suppressions", &self.address))
+            .bearer_auth(&self.admin_api_token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_suppression(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/suppressions/{}", &self.address, email))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn import_suppressions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/suppressions/import", &self.address))
+            .bearer_auth(&self.admin_api_token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn request_data_export(&self, subscription_token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/me/export", &self.address))
+            .form(&[("subscription_token", subscription_token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscriber_export(&self, subscriber_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/subscribers/{}/export",
+                &self.address, subscriber_id
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn erase_subscriber(&self, subscriber_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .bearer_auth(&self.admin_api_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub fn get_subscription_link(&self, email_request: &wiremock::Request) -> String {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let finder = LinkFinder::new();
         let links: Vec<_> = finder
-            .links(&body["htmlContent"].as_str().unwrap())
+            .links(body["htmlContent"].as_str().unwrap())
             .collect();
 
         let link = links[0].as_str();
@@ -70,44 +335,63 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Same as `spawn_app`, letting the test adjust the configuration once the
+/// test database exists but before the application is built.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
 
-    let configuration = {
+    let mut configuration = {
         let mut config = get_configuration().expect("Failed to read configuration.");
         // Use a different database for each test case
         config.database.database_name = Uuid::new_v4().to_string();
         // Use a random OS port
         config.application.port = 0;
         config.email_client.base_url = email_server.uri();
+        // Flush tracked opens quickly so tests do not wait on the buffer
+        config.tracking.flush_interval_milliseconds = 50;
         config
     };
     configure_database(&configuration.database).await;
+    customise(&mut configuration);
 
     let application = Application::build(configuration.clone())
         .await
         .expect("Failed to build application");
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application_port);
-    let _ = tokio::spawn(application.run());
+    let metrics_address = application
+        .metrics_port()
+        .map(|port| format!("http://127.0.0.1:{}", port));
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(application.run_until_stopped(async {
+        let _ = stopped.await;
+    }));
 
     TestApp {
         address,
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
+        webhook_secret: configuration
+            .webhooks
+            .shared_secret
+            .expose_secret()
+            .to_owned(),
+        admin_api_token: configuration.admin.api_token.expose_secret().to_owned(),
+        metrics_address,
+        configuration,
+        shutdown: Some(shutdown),
+        server: Some(server),
     }
 }
 
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres");
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database");
+    create_database(config).await;
 
     let connection_pool = PgPool::connect_with(config.with_db())
         .await
@@ -120,3 +404,14 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
     connection_pool
 }
+
+/// Creates an empty database, without running the migrations.
+pub async fn create_database(config: &DatabaseSettings) {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create database");
+}
@@ -0,0 +1,198 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn publish_issue(app: &TestApp) -> (u16, Option<String>) {
+    let response = app.post_newsletter(newsletter_request_body()).await;
+    let status = response.status().as_u16();
+    let newsletter_issue_id = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body["newsletter_issue_id"].as_str().map(str::to_owned));
+    (status, newsletter_issue_id)
+}
+
+#[tokio::test]
+async fn sent_deliveries_are_recorded_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(201)
+                .set_body_json(serde_json::json!({ "messageId": "<abc@brevo>" })),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (status, newsletter_issue_id) = publish_issue(&app).await;
+    assert_eq!(status, 200);
+
+    let report: serde_json::Value = app
+        .get_issue_deliveries(&newsletter_issue_id.unwrap(), "")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["counts"]["sent"], 1);
+    assert_eq!(report["counts"]["total"], 1);
+    let delivery = &report["deliveries"][0];
+    assert_eq!(delivery["subscriber_email"], "reader@example.com");
+    assert_eq!(delivery["status"], "sent");
+    assert_eq!(delivery["provider_message_id"], "<abc@brevo>");
+}
+
+#[tokio::test]
+async fn invalid_stored_emails_are_recorded_as_skipped() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("not-an-email").await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let (status, newsletter_issue_id) = publish_issue(&app).await;
+    assert_eq!(status, 200);
+
+    let report: serde_json::Value = app
+        .get_issue_deliveries(
+            &newsletter_issue_id.unwrap(),
+            "status=skipped_invalid_email",
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["counts"]["skipped_invalid_email"], 1);
+    let delivery = &report["deliveries"][0];
+    assert_eq!(delivery["subscriber_email"], "not-an-email");
+    assert!(delivery["failure_reason"].as_str().is_some());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_recorded_with_a_reason() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (status, newsletter_issue_id) = publish_issue(&app).await;
+    assert_eq!(status, 202);
+    assert!(newsletter_issue_id.is_some());
+
+    let delivery = sqlx::query!("SELECT status, failure_reason FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "failed");
+    assert!(delivery.failure_reason.is_some());
+}
+
+#[tokio::test]
+async fn deliveries_are_paginated() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("a@example.com").await;
+    app.insert_confirmed_subscriber("b@example.com").await;
+    app.insert_confirmed_subscriber("c@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&app.email_server)
+        .await;
+
+    let (_, newsletter_issue_id) = publish_issue(&app).await;
+    let newsletter_issue_id = newsletter_issue_id.unwrap();
+
+    let second_page: serde_json::Value = app
+        .get_issue_deliveries(&newsletter_issue_id, "page=2&page_size=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(second_page["counts"]["total"], 3);
+    let deliveries = second_page["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["subscriber_email"], "c@example.com");
+}
+
+#[tokio::test]
+async fn deliveries_of_an_unknown_issue_are_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_deliveries("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a", "")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn invalid_pagination_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let (_, newsletter_issue_id) = publish_issue(&app).await;
+    let newsletter_issue_id = newsletter_issue_id.unwrap();
+
+    for query in ["page=0", "page_size=0", "page_size=100000"] {
+        let response = app.get_issue_deliveries(&newsletter_issue_id, query).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the query was {}.",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn issue_and_subscriber_reports_require_the_admin_api_token() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = uuid::Uuid::new_v4();
+
+    for route in [
+        format!("/admin/issues/{}/deliveries", newsletter_issue_id),
+        format!("/admin/issues/{}/opens", newsletter_issue_id),
+        format!("/admin/issues/{}/clicks", newsletter_issue_id),
+        format!("/admin/issues/{}/stats", newsletter_issue_id),
+        "/admin/reports/subscribers".to_string(),
+    ] {
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", app.address, route))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 401, "{}", route);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "unauthorized");
+    }
+}
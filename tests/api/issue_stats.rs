@@ -0,0 +1,169 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publishes a tracked issue to two subscribers, then has the first one
+/// receive, open and click it while the second one hard-bounces.
+async fn publish_and_engage(app: &TestApp) -> String {
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    app.insert_confirmed_subscriber("bouncer@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(201)
+                .set_body_json(serde_json::json!({ "messageIds": ["<first>", "<second>"] })),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": r#"<a href="https://example.com/post">Read, now</a>"#,
+            },
+            "track_opens": true,
+            "track_clicks": true,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+
+    let deliveries =
+        sqlx::query!("SELECT subscriber_email, provider_message_id FROM issue_deliveries")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    for delivery in deliveries {
+        let event = if delivery.subscriber_email == "reader@example.com" {
+            "delivered"
+        } else {
+            "hard_bounce"
+        };
+        let response = app
+            .post_email_event(
+                serde_json::json!({
+                    "event": event,
+                    "email": delivery.subscriber_email,
+                    "message-id": delivery.provider_message_id,
+                }),
+                &app.webhook_secret,
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let reader = body["messageVersions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|v| v["to"][0]["email"] == "reader@example.com")
+        .unwrap();
+    let html = reader["htmlContent"].as_str().unwrap();
+    for prefix in ["/t/o/", "/t/c/"] {
+        let start = html.find(prefix).unwrap();
+        let end = start + html[start..].find('"').unwrap();
+        app.get_tracked_link(&html[start..end]).await;
+    }
+
+    newsletter_issue_id
+}
+
+/// Tracking events are written in the background, so poll until they land.
+async fn wait_for_stats(app: &TestApp, newsletter_issue_id: &str) -> serde_json::Value {
+    for _ in 0..50 {
+        let stats: serde_json::Value = app
+            .get_issue_stats(newsletter_issue_id, "")
+            .await
+            .json()
+            .await
+            .unwrap();
+        if stats["counts"]["opened"] == 1 && stats["counts"]["clicked"] == 1 {
+            return stats;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("Tracking events were not recorded in time");
+}
+
+#[tokio::test]
+async fn stats_aggregate_deliveries_tracking_and_provider_events() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_and_engage(&app).await;
+
+    let stats = wait_for_stats(&app, &newsletter_issue_id).await;
+
+    let counts = &stats["counts"];
+    assert_eq!(counts["sent"], 2);
+    assert_eq!(counts["delivered"], 1);
+    assert_eq!(counts["bounced"], 1);
+    assert_eq!(counts["unsubscribed"], 0);
+    let rates = &stats["rates"];
+    assert_eq!(rates["delivery_rate"], 0.5);
+    assert_eq!(rates["open_rate"], 0.5);
+    assert_eq!(rates["click_to_open_rate"], 1.0);
+    let timeline = stats["timeline"].as_array().unwrap();
+    assert_eq!(timeline.len(), 1);
+    assert_eq!(timeline[0]["opens"], 1);
+    assert_eq!(timeline[0]["clicks"], 1);
+    assert_eq!(stats["top_links"][0]["url"], "https://example.com/post");
+}
+
+#[tokio::test]
+async fn stats_can_be_exported_as_csv() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_and_engage(&app).await;
+    wait_for_stats(&app, &newsletter_issue_id).await;
+
+    let response = app
+        .get_issue_stats(&newsletter_issue_id, "format=csv&bucket=day")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "section,key,metric,value");
+    assert!(lines.contains(&"count,,sent,2"));
+    assert!(lines.contains(&"rate,,bounce_rate,0.5"));
+    assert!(lines.contains(&"link,https://example.com/post,total_clicks,1"));
+    assert_eq!(
+        lines.iter().filter(|l| l.starts_with("timeline,")).count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn invalid_stats_queries_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_and_engage(&app).await;
+
+    for query in ["bucket=minute", "format=xml"] {
+        let response = app.get_issue_stats(&newsletter_issue_id, query).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the query was {}.",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn stats_of_an_unknown_issue_are_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_stats("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a", "")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
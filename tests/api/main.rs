@@ -1,5 +1,22 @@
+mod admin_cli;
+mod audit_log;
+mod click_tracking;
+mod data_export;
+mod erasure;
+mod error_responses;
 mod health_check;
 mod helpers;
+mod issue_deliveries;
+mod issue_stats;
+mod migrations;
+mod monitoring;
 mod newsletter;
+mod open_tracking;
+mod request_id;
+mod shutdown;
+mod subscriber_reports;
 mod subscription_confirm;
 mod subscriptions;
+mod suppressions;
+mod trace_propagation;
+mod webhooks;
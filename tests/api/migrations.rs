@@ -0,0 +1,101 @@
+use crate::helpers::create_database;
+use sqlx::PgPool;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::migrations::{run_migrations, MIGRATOR};
+use zero2prod::startup::{get_connection_pool, Application};
+
+/// Settings pointing at a new, empty database.
+async fn unmigrated_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.port = 0;
+    create_database(&configuration.database).await;
+    configuration
+}
+
+async fn applied_migrations(pool: &PgPool) -> Vec<i64> {
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+fn embedded_migrations() -> Vec<i64> {
+    MIGRATOR.iter().map(|m| m.version).collect()
+}
+
+#[tokio::test]
+async fn migrations_run_on_startup_when_enabled() {
+    let mut configuration = unmigrated_configuration().await;
+    configuration.application.run_migrations_on_startup = true;
+
+    Application::build(configuration.clone())
+        .await
+        .expect("Failed to build application");
+
+    let pool = get_connection_pool(&configuration.database);
+    assert_eq!(applied_migrations(&pool).await, embedded_migrations());
+}
+
+#[tokio::test]
+async fn migrations_are_not_run_on_startup_by_default() {
+    let configuration = unmigrated_configuration().await;
+
+    Application::build(configuration.clone())
+        .await
+        .expect("Failed to build application");
+
+    let pool = get_connection_pool(&configuration.database);
+    let tables: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = 'public'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(tables, 0);
+}
+
+#[tokio::test]
+async fn instances_migrating_together_take_turns() {
+    let configuration = unmigrated_configuration().await;
+
+    let outcomes =
+        futures::future::join_all((0..4).map(|_| run_migrations(&configuration.database))).await;
+
+    for outcome in outcomes {
+        outcome.expect("Failed to migrate");
+    }
+    let pool = get_connection_pool(&configuration.database);
+    assert_eq!(applied_migrations(&pool).await, embedded_migrations());
+}
+
+#[tokio::test]
+async fn the_migrate_subcommand_migrates_the_database() {
+    let configuration = unmigrated_configuration().await;
+    let overlay = std::env::temp_dir().join(format!("{}.yaml", Uuid::new_v4()));
+    std::fs::write(
+        &overlay,
+        format!(
+            "database:\n  database_name: \"{}\"\n",
+            configuration.database.database_name
+        ),
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_zero2prod"))
+        .arg("migrate")
+        .arg("--config")
+        .arg(&overlay)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pool = get_connection_pool(&configuration.database);
+    assert_eq!(applied_migrations(&pool).await, embedded_migrations());
+    std::fs::remove_file(overlay).unwrap();
+}
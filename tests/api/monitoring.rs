@@ -0,0 +1,78 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_format() {
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    let response = app.get_metrics().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(
+        body.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#)
+    );
+    assert!(body.contains("http_request_duration_seconds_bucket{"));
+    assert!(body.contains("db_pool_connections "));
+    assert!(body.contains("db_pool_idle_connections "));
+    assert!(body.contains("delivery_queue_depth "));
+    assert!(body.contains("delivery_queue_oldest_age_seconds "));
+}
+
+#[tokio::test]
+async fn requests_are_labelled_by_route_pattern_not_path() {
+    let app = spawn_app().await;
+    app.get_issue_deliveries("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a", "")
+        .await;
+
+    let body = app.get_metrics().await.text().await.unwrap();
+
+    assert!(body.contains(r#"route="/admin/issues/{newsletter_issue_id}/deliveries",status="404""#));
+    assert!(!body.contains("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a"));
+}
+
+#[tokio::test]
+async fn email_provider_requests_are_counted_by_outcome() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    let body = app.get_metrics().await.text().await.unwrap();
+
+    assert!(body.contains(r#"email_client_requests_total{outcome="success"}"#));
+    assert!(body.contains(r#"email_client_request_duration_seconds_count{outcome="success"}"#));
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_separate_port() {
+    let app = spawn_app_with(|config| config.application.metrics_port = Some(0)).await;
+
+    let public = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+    let private = app.get_metrics().await;
+
+    assert_eq!(public.status().as_u16(), 404);
+    assert_eq!(private.status().as_u16(), 200);
+}
@@ -1,6 +1,8 @@
-use crate::helpers::{spawn_app, TestApp};
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use futures::TryStreamExt;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::get_confirmed_subscribers;
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -60,6 +62,125 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     assert_eq!(response.status().as_u16(), 200);
 }
 
+#[tokio::test]
+async fn newsletter_delivery_backs_off_when_the_provider_rate_limits() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+             "title": "Newsletter title",
+             "content": {
+                 "text": "Newsletter body as plain text",
+                 "html": "<p>Newsletter body as HTML</p>",
+             }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_batched_into_message_versions() {
+    let app = spawn_app().await;
+
+    app.insert_confirmed_subscriber("first@example.com").await;
+    app.insert_confirmed_subscriber("second@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+             "title": "Newsletter title",
+             "content": {
+                 "text": "Newsletter body as plain text",
+                 "html": "<p>Newsletter body as HTML</p>",
+             }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let mut recipients: Vec<_> = body["messageVersions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|version| version["to"][0]["email"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, ["first@example.com", "second@example.com"]);
+    assert_eq!(body["htmlContent"], "<p>Newsletter body as HTML</p>");
+}
+
+#[tokio::test]
+async fn partially_failed_newsletters_return_the_issue_with_a_202() {
+    let app = spawn_app_with(|c| {
+        c.email_client.batch_size = 1;
+        c.email_client.max_concurrent_requests = 1;
+        c.email_client.max_retries = 0;
+    })
+    .await;
+    app.insert_confirmed_subscriber("first@example.com").await;
+    app.insert_confirmed_subscriber("second@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "partial");
+    assert_eq!(body["sent"], 1);
+    assert_eq!(body["failed"], 1);
+    let issues = sqlx::query!(r#"SELECT newsletter_issue_id FROM newsletter_issues"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(
+        body["newsletter_issue_id"],
+        issues[0].newsletter_issue_id.to_string()
+    );
+}
+
 #[tokio::test]
 async fn newsletter_returns_400_for_invalid_data() {
     let app = spawn_app().await;
@@ -126,3 +247,44 @@ async fn create_confirmed_subscriber(app: &TestApp) {
         .error_for_status()
         .unwrap();
 }
+
+/// Seeds 500k confirmed subscribers and walks them with
+/// `get_confirmed_subscribers`.
+///
+/// Run it explicitly with
+/// `cargo test --release streaming_500k -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore]
+async fn streaming_500k_confirmed_subscribers() {
+    let app = spawn_app().await;
+
+    sqlx::query(
+        r#"
+        INSERT INTO subscriptions (id, email, email_hash, name, subscribed_at, status)
+        SELECT gen_random_uuid(), e.email,
+            encode(sha256(convert_to(e.email, 'UTF8')), 'hex'), 'subscriber ' || n, now(),
+            'confirmed'
+        FROM generate_series(1, 500000) AS n,
+            LATERAL (SELECT 'subscriber' || n || '@example.com' AS email) AS e
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to seed subscribers");
+
+    let start = std::time::Instant::now();
+    let streamed = get_confirmed_subscribers(&app.db_pool)
+        .try_fold(0usize, |count, subscriber| async move {
+            subscriber?;
+            Ok(count + 1)
+        })
+        .await
+        .expect("Failed to stream confirmed subscribers");
+
+    println!(
+        "Streamed {} confirmed subscribers in {:?}",
+        streamed,
+        start.elapsed()
+    );
+    assert_eq!(streamed, 500_000);
+}
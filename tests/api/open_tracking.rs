@@ -0,0 +1,144 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body(track_opens: bool) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "track_opens": track_opens,
+    })
+}
+
+async fn publish_issue(app: &TestApp, track_opens: bool) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(newsletter_request_body(track_opens))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// Extracts the path of the tracking pixel from the email sent to the
+/// provider, if there is one.
+async fn sent_pixel_path(app: &TestApp) -> Option<String> {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["htmlContent"].as_str().unwrap();
+    let start = html.find("/t/o/")?;
+    let end = start + html[start..].find('"')?;
+    Some(html[start..end].to_owned())
+}
+
+/// Open events are written in the background, so poll until they land.
+async fn wait_for_opens(app: &TestApp, newsletter_issue_id: &str, total: i64) -> serde_json::Value {
+    for _ in 0..50 {
+        let report: serde_json::Value = app
+            .get_issue_opens(newsletter_issue_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        if report["total_opens"] == total {
+            return report;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("Open events were not recorded in time");
+}
+
+#[tokio::test]
+async fn tracked_issues_embed_a_pixel_that_records_opens() {
+    let app = spawn_app().await;
+    let subscriber_id = app.insert_confirmed_subscriber("reader@example.com").await;
+    let newsletter_issue_id = publish_issue(&app, true).await;
+    let pixel_path = sent_pixel_path(&app)
+        .await
+        .expect("The email did not embed a tracking pixel");
+
+    let response = app.get_tracking_pixel(&pixel_path).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "image/gif");
+    assert!(response.headers()["cache-control"]
+        .to_str()
+        .unwrap()
+        .contains("no-store"));
+    wait_for_opens(&app, &newsletter_issue_id, 1).await;
+    let open = sqlx::query!("SELECT subscriber_id, user_agent FROM open_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(open.subscriber_id, subscriber_id);
+    assert_eq!(open.user_agent.as_deref(), Some("test-mail-client"));
+}
+
+#[tokio::test]
+async fn repeated_opens_count_towards_total_but_not_unique_opens() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    let newsletter_issue_id = publish_issue(&app, true).await;
+    let pixel_path = sent_pixel_path(&app).await.unwrap();
+
+    app.get_tracking_pixel(&pixel_path).await;
+    app.get_tracking_pixel(&pixel_path).await;
+
+    let report = wait_for_opens(&app, &newsletter_issue_id, 2).await;
+    assert_eq!(report["unique_opens"], 1);
+    assert_eq!(report["track_opens"], true);
+}
+
+#[tokio::test]
+async fn issues_do_not_embed_a_pixel_unless_they_opt_in() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    let newsletter_issue_id = publish_issue(&app, false).await;
+
+    assert_eq!(sent_pixel_path(&app).await, None);
+    let report: serde_json::Value = app
+        .get_issue_opens(&newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["track_opens"], false);
+    assert_eq!(report["total_opens"], 0);
+}
+
+#[tokio::test]
+async fn invalid_tracking_tokens_still_get_a_pixel_but_record_nothing() {
+    let app = spawn_app().await;
+
+    let response = app.get_tracking_pixel("/t/o/forged.token.gif").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "image/gif");
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let opens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM open_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(opens.count, 0);
+}
+
+#[tokio::test]
+async fn opens_of_an_unknown_issue_are_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_opens("3e0c8d3c-7c6f-4a86-9d5a-1d1f0b7c2b3a")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,137 @@
+use crate::helpers::spawn_app;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SUBSCRIBE_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_none_is_given() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn a_valid_caller_request_id_is_echoed_back() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.headers()["x-request-id"], "support-ticket-42");
+}
+
+#[tokio::test]
+async fn an_invalid_caller_request_id_is_replaced() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .header("X-Request-Id", "not a valid id")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn error_bodies_carry_the_request_id() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "bad-signup")
+        .body("name=&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["x-request-id"], "bad-signup");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], "bad-signup");
+}
+
+#[tokio::test]
+async fn the_request_id_is_stamped_on_the_confirmation_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "signup-1")
+        .body(SUBSCRIBE_BODY)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(
+        email_request
+            .headers
+            .get(&"x-request-id".into())
+            .unwrap()
+            .as_str(),
+        "signup-1"
+    );
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["headers"]["X-Request-Id"], "signup-1");
+}
+
+#[tokio::test]
+async fn queued_deliveries_record_the_publish_request_id() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .header("X-Request-Id", "publish-1")
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    let report: serde_json::Value = app
+        .get_issue_deliveries(newsletter_issue_id, "")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["deliveries"][0]["request_id"], "publish-1");
+}
@@ -0,0 +1,168 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body(track_opens: bool) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "track_opens": track_opens,
+    })
+}
+
+async fn delivery_statuses(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.status)
+        .collect()
+}
+
+#[tokio::test]
+async fn in_flight_deliveries_complete_before_shutdown() {
+    let mut app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let address = app.address.clone();
+    let publish = tokio::spawn(async move {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", address))
+            .json(&newsletter_request_body(false))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    });
+    // Shut down while the provider call is under way.
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    app.shutdown().await;
+
+    let response = publish.await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_statuses(&app).await, ["sent"]);
+}
+
+#[tokio::test]
+async fn deliveries_cut_short_by_shutdown_are_failed_and_retried() {
+    // Three batches, sent one at a time, each outlasting the shutdown
+    // timeout.
+    let mut app = spawn_app_with(|c| {
+        c.application.shutdown_timeout_seconds = 2;
+        c.email_client.batch_size = 2;
+        c.email_client.max_concurrent_requests = 1;
+    })
+    .await;
+    for i in 0..6 {
+        app.insert_confirmed_subscriber(&format!("reader{}@example.com", i))
+            .await;
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_delay(Duration::from_secs(5)))
+        .mount(&app.email_server)
+        .await;
+
+    let address = app.address.clone();
+    let publish = tokio::spawn(async move {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", address))
+            .json(&newsletter_request_body(false))
+            .send()
+            .await
+    });
+    // Shut down while the first batch is with the provider.
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    app.shutdown().await;
+    let response = publish.await.unwrap().expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "partial");
+    assert_eq!(body["failed"], 6);
+
+    let statuses = delivery_statuses(&app).await;
+    assert_eq!(statuses, ["failed"; 6], "{:?}", statuses);
+
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    let retried = app.admin_json(&["queue", "retry-failed"]).await;
+
+    assert_eq!(retried[0]["retried"], 6);
+    assert_eq!(delivery_statuses(&app).await, ["sent"; 6]);
+}
+
+#[tokio::test]
+async fn no_connections_are_accepted_after_shutdown() {
+    let mut app = spawn_app().await;
+
+    app.shutdown().await;
+
+    let result = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .timeout(Duration::from_secs(1))
+        .send()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn buffered_tracking_events_are_flushed_on_shutdown() {
+    // Never flush on a timer, so only shutdown can write the event.
+    let mut app = spawn_app_with(|c| c.tracking.flush_interval_milliseconds = 3_600_000).await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletter(newsletter_request_body(true)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["htmlContent"].as_str().unwrap();
+    let start = html.find("/t/o/").expect("No tracking pixel was sent");
+    let end = start + html[start..].find('"').unwrap();
+    let pixel_path = &html[start..end];
+    let response = app.get_tracking_pixel(pixel_path).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.shutdown().await;
+
+    let opens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM open_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(opens.count, 1);
+}
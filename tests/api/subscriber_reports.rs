@@ -0,0 +1,156 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Datelike, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Signs up two subscribers, confirms the first and has them unsubscribe.
+async fn sign_up_confirm_and_unsubscribe(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=octavia&email=octavia_butler%40gmail.com".into())
+        .await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_subscription_link(email_request);
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app
+        .post_email_event(
+            serde_json::json!({
+                "event": "unsubscribed",
+                "email": "ursula_le_guin@gmail.com",
+            }),
+            &app.webhook_secret,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn status_transitions_are_recorded() {
+    let app = spawn_app().await;
+
+    sign_up_confirm_and_unsubscribe(&app).await;
+
+    let transitions = sqlx::query!(
+        r#"
+        SELECT c.from_status, c.to_status
+        FROM subscription_status_changes c
+        JOIN subscriptions s ON s.id = c.subscriber_id
+        WHERE s.email = 'ursula_le_guin@gmail.com'
+        ORDER BY c.changed_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let transitions: Vec<_> = transitions
+        .into_iter()
+        .map(|t| (t.from_status, t.to_status))
+        .collect();
+    assert_eq!(
+        transitions,
+        [
+            (None, "pending_confirmation".to_string()),
+            (
+                Some("pending_confirmation".to_string()),
+                "confirmed".to_string()
+            ),
+            (Some("confirmed".to_string()), "unsubscribed".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn report_counts_signups_confirmations_and_unsubscribes_per_day() {
+    let app = spawn_app().await;
+    sign_up_confirm_and_unsubscribe(&app).await;
+    let today = Utc::today().naive_utc();
+
+    let report: serde_json::Value = app
+        .get_subscriber_report(&format!("from={}&to={}&bucket=day", today, today))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let buckets = report["buckets"].as_array().unwrap();
+    assert_eq!(buckets.len(), 1);
+    let bucket = &buckets[0];
+    assert_eq!(bucket["bucket_start"], format!("{}T00:00:00", today));
+    assert_eq!(bucket["signups"], 2);
+    assert_eq!(bucket["confirmations"], 1);
+    assert_eq!(bucket["confirmation_rate"], 0.5);
+    assert_eq!(bucket["unsubscribes"], 1);
+    assert_eq!(bucket["churned"], 1);
+    assert_eq!(bucket["net_growth"], 0);
+    assert_eq!(report["totals"]["signups"], 2);
+}
+
+#[tokio::test]
+async fn week_buckets_start_on_monday() {
+    let app = spawn_app().await;
+    let today = Utc::today().naive_utc();
+    let monday = today - chrono::Duration::days(today.weekday().num_days_from_monday().into());
+
+    let report: serde_json::Value = app
+        .get_subscriber_report(&format!("from={}&to={}&bucket=week", today, today))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report["buckets"][0]["bucket_start"],
+        format!("{}T00:00:00", monday)
+    );
+    assert_eq!(report["buckets"][0]["signups"], 0);
+}
+
+#[tokio::test]
+async fn empty_buckets_are_reported_with_zero_counts() {
+    let app = spawn_app().await;
+
+    let report: serde_json::Value = app
+        .get_subscriber_report("from=2022-01-01&to=2022-03-31&bucket=month")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let buckets = report["buckets"].as_array().unwrap();
+    assert_eq!(buckets.len(), 3);
+    assert_eq!(buckets[2]["bucket_start"], "2022-03-01T00:00:00");
+    assert_eq!(report["totals"]["net_growth"], 0);
+    assert_eq!(report["totals"]["confirmation_rate"], 0.0);
+}
+
+#[tokio::test]
+async fn invalid_report_queries_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for query in [
+        "bucket=year",
+        "from=2022-02-01&to=2022-01-01",
+        "from=2000-01-01&to=2022-01-01",
+        "from=yesterday",
+    ] {
+        let response = app.get_subscriber_report(query).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the query was {}.",
+            query
+        );
+    }
+}
@@ -98,7 +98,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let link = app.get_subscription_link(email_request);
 
-    assert_eq!(link.is_empty(), false)
+    assert!(!link.is_empty())
 }
 
 #[tokio::test]
@@ -0,0 +1,313 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::suppression::{add_suppression, SuppressionReason};
+
+#[tokio::test]
+async fn suppressed_subscribers_are_skipped_and_recorded() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    app.post_suppression(serde_json::json!({
+        "email": "Reader@Example.com",
+        "reason": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let delivery = sqlx::query!("SELECT status, failure_reason FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped_suppressed");
+    assert!(delivery.failure_reason.unwrap().contains("manual"));
+}
+
+#[tokio::test]
+async fn suppressions_match_addresses_with_non_ascii_capitals() {
+    let app = spawn_app().await;
+    // Postgres lowercases `İ` to `i`, Rust to `i` and a combining dot.
+    app.insert_confirmed_subscriber("İNCİ@example.com").await;
+    add_suppression(
+        &app.db_pool,
+        "İNCİ@example.com",
+        SuppressionReason::Bounce,
+        "webhook",
+    )
+    .await
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped_suppressed");
+}
+
+#[tokio::test]
+async fn suppressed_addresses_do_not_get_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({
+        "email": "ursula_le_guin@gmail.com",
+        "reason": "legal",
+        "source": "support ticket 42",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let skipped = sqlx::query!("SELECT status, reason FROM skipped_confirmation_emails")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(skipped.status, "skipped_suppressed");
+    assert_eq!(skipped.reason, "The address is suppressed (legal)");
+}
+
+#[tokio::test]
+async fn failed_deliveries_to_addresses_suppressed_since_are_skipped_on_retry() {
+    let app = spawn_app_with(|c| c.email_client.max_retries = 0).await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.post_suppression(serde_json::json!({
+        "email": "reader@example.com",
+        "reason": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    app.admin(&["queue", "retry-failed"]).await.unwrap();
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped_suppressed");
+}
+
+#[tokio::test]
+async fn removed_suppressions_are_mailed_again() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    app.post_suppression(serde_json::json!({
+        "email": "reader@example.com",
+        "reason": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app.delete_suppression("reader@example.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn removing_an_unknown_suppression_is_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.delete_suppression("reader@example.com").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn suppressions_are_rejected_with_a_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"email": "not-an-email", "reason": "manual"}),
+            "invalid email",
+        ),
+        (
+            serde_json::json!({"email": "reader@example.com", "reason": "boredom"}),
+            "unknown reason",
+        ),
+        (serde_json::json!({"reason": "manual"}), "missing email"),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_suppression(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn bulk_imports_report_new_and_existing_suppressions() {
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({
+        "email": "first@example.com",
+        "reason": "bounce",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app
+        .import_suppressions(serde_json::json!({
+            "suppressions": [
+                {"email": "first@example.com", "reason": "manual"},
+                {"email": "second@example.com", "reason": "complaint", "source": "legacy list"},
+                {"email": "third@example.com", "reason": "legal"},
+            ]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["added"], 2);
+    assert_eq!(summary["already_suppressed"], 1);
+
+    let stored = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 3);
+}
+
+#[tokio::test]
+async fn bulk_imports_with_an_invalid_entry_are_rejected_as_a_whole() {
+    let app = spawn_app().await;
+
+    let response = app
+        .import_suppressions(serde_json::json!({
+            "suppressions": [
+                {"email": "first@example.com", "reason": "manual"},
+                {"email": "not-an-email", "reason": "manual"},
+            ]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let stored = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 0);
+}
+
+#[tokio::test]
+async fn suppressions_cannot_be_changed_without_the_admin_api_token() {
+    let app = spawn_app().await;
+    add_suppression(
+        &app.db_pool,
+        "complained@example.com",
+        SuppressionReason::Complaint,
+        "webhook",
+    )
+    .await
+    .unwrap();
+    let client = reqwest::Client::new();
+    let suppression = serde_json::json!({ "email": "reader@example.com", "reason": "manual" });
+
+    for request in [
+        client
+            .post(format!("{}/admin/suppressions", app.address))
+            .json(&suppression),
+        client.delete(format!(
+            "{}/admin/suppressions/complained@example.com",
+            app.address
+        )),
+        client
+            .post(format!("{}/admin/suppressions/import", app.address))
+            .bearer_auth("not-the-token")
+            .json(&serde_json::json!({ "suppressions": [suppression] })),
+    ] {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], "unauthorized");
+    }
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM suppressions) AS "suppressions!",
+            (SELECT COUNT(*) FROM audit_log) AS "audit_entries!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(counts.suppressions, 1);
+    assert_eq!(counts.audit_entries, 0);
+}
@@ -0,0 +1,72 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+async fn subscribe_with_headers(
+    app: &crate::helpers::TestApp,
+    traceparent: Option<&str>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    if let Some(traceparent) = traceparent {
+        request = request.header("traceparent", traceparent);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+fn outgoing_traceparent(request: &wiremock::Request) -> String {
+    request
+        .headers
+        .get(&"traceparent".into())
+        .expect("The email provider request has no traceparent")
+        .as_str()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn incoming_trace_context_is_propagated_to_the_email_provider() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let incoming = format!("00-{}-00f067aa0ba902b7-01", TRACE_ID);
+
+    let response = subscribe_with_headers(&app, Some(&incoming)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = outgoing_traceparent(email_request);
+    let parts: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[1], TRACE_ID);
+    // The provider call is a child span of ours, not of the caller's.
+    assert_ne!(parts[2], "00f067aa0ba902b7");
+    assert_eq!(parts[3], "01");
+}
+
+#[tokio::test]
+async fn a_new_trace_is_started_without_an_incoming_trace_context() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = subscribe_with_headers(&app, None).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = outgoing_traceparent(email_request);
+    let parts: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(parts.len(), 4);
+    assert_ne!(parts[1], "0".repeat(32));
+}
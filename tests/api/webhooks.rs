@@ -0,0 +1,232 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_issue_with_message_id(app: &TestApp, message_id: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(201)
+                .set_body_json(serde_json::json!({ "messageId": message_id })),
+        )
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+fn event(kind: &str, email: &str, message_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "event": kind,
+        "email": email,
+        "id": 1,
+        "date": "2022-10-19 10:00:00",
+        "message-id": message_id,
+        "ts_event": 1666173600,
+    })
+}
+
+#[tokio::test]
+async fn events_without_the_shared_secret_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_event(
+            event("delivered", "reader@example.com", "<abc@brevo>"),
+            "not-the-secret",
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_event(
+            serde_json::json!({ "email": "reader@example.com" }),
+            &app.webhook_secret,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn delivered_events_update_the_delivery_record() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    publish_issue_with_message_id(&app, "<abc@brevo>").await;
+
+    let response = app
+        .post_email_event(
+            event("delivered", "reader@example.com", "<abc@brevo>"),
+            &app.webhook_secret,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "delivered");
+
+    let stored = sqlx::query!("SELECT event, newsletter_issue_id FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.event, "delivered");
+    assert!(stored.newsletter_issue_id.is_some());
+}
+
+#[tokio::test]
+async fn late_events_do_not_move_a_delivery_back() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    publish_issue_with_message_id(&app, "<abc@brevo>").await;
+
+    for kind in ["spam", "delivered", "soft_bounce"] {
+        let response = app
+            .post_email_event(
+                event(kind, "reader@example.com", "<abc@brevo>"),
+                &app.webhook_secret,
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "complained");
+    let linked = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM email_events WHERE newsletter_issue_id IS NOT NULL"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(linked.count, 3);
+}
+
+#[tokio::test]
+async fn soft_bounces_do_not_replace_a_delivery() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    publish_issue_with_message_id(&app, "<abc@brevo>").await;
+
+    for kind in ["soft_bounce", "delivered", "soft_bounce"] {
+        app.post_email_event(
+            event(kind, "reader@example.com", "<abc@brevo>"),
+            &app.webhook_secret,
+        )
+        .await;
+    }
+
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "delivered");
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+    publish_issue_with_message_id(&app, "<abc@brevo>").await;
+
+    let response = app
+        .post_email_event(
+            event("hard_bounce", "reader@example.com", "<abc@brevo>"),
+            &app.webhook_secret,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "bounced");
+    let suppression = sqlx::query!("SELECT reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.reason, "bounce");
+    assert_eq!(suppression.source, "webhook");
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "bounced");
+
+    // A bounced subscriber no longer receives newsletter issues
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn spam_complaints_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    app.insert_confirmed_subscriber("reader@example.com").await;
+
+    let response = app
+        .post_email_event(
+            event("spam", "reader@example.com", "<unknown@brevo>"),
+            &app.webhook_secret,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "complained");
+}
+
+#[tokio::test]
+async fn unsupported_events_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_event(
+            event("proxy_open", "reader@example.com", "<abc@brevo>"),
+            &app.webhook_secret,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let stored = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 0);
+}